@@ -1,144 +1,298 @@
-use std::collections::HashMap;
+pub type Word = u128;
+
+pub const MAX_WIDTH: u32 = Word::BITS;
+
+fn width_mask(width: u32) -> Word {
+    Word::MAX >> (MAX_WIDTH - width)
+}
 
 #[derive(Debug, Clone)]
 pub struct Mask {
-    bitwise_and: usize,
-    bitwise_or: usize,
+    bitwise_and: Word,
+    bitwise_or: Word,
 }
 
 #[derive(Debug)]
 pub enum Instruction {
     Mask(Mask),
     Memory {
-        address: usize,
-        value: usize,
+        address: Word,
+        value: Word,
     },
 }
 
-pub fn generator(input: &str) -> Vec<Instruction> {
-    let pattern = regex::Regex::new(r"^(?:mask|mem\[(\d+)\]) = (.*)$").unwrap();
-    input.lines().map(|line| {
-        let m = pattern.captures(line).unwrap();
-        match m.get(1) {
-            None => {
-                let mut bitwise_and = 0;
-                let mut bitwise_or = 0;
-                for c in m[2].chars() {
-                    bitwise_and <<= 1;
-                    bitwise_or <<= 1;
-                    match c {
-                        '0' => {
-                        },
-                        '1' => {
-                            bitwise_and |= 1;
-                            bitwise_or |= 1;
-                        },
-                        'X' => {
-                            bitwise_and |= 1;
-                        },
-                        _ => panic!("Illegal mask character {:?}", c),
-                    }
-                }
-                Instruction::Mask(Mask {
-                    bitwise_and,
-                    bitwise_or,
-                })
+#[derive(Debug)]
+pub struct Program {
+    width: u32,
+    instructions: Vec<Instruction>,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    MalformedLine(String),
+    IllegalMaskCharacter(char),
+    MaskTooLong {
+        length: usize,
+        width: u32,
+    },
+    NumberTooWide {
+        number: String,
+        width: u32,
+    },
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ParseError::MalformedLine(line) => write!(f, "Malformed line {:?}", line),
+            ParseError::IllegalMaskCharacter(c) => write!(f, "Illegal mask character {:?}", c),
+            ParseError::MaskTooLong { length, width } => write!(f, "Mask has {} bits but the word width is {}", length, width),
+            ParseError::NumberTooWide { number, width } => write!(f, "{} does not fit in {} bits", number, width),
+        }
+    }
+}
+
+fn parse_mask(text: &str, width: u32) -> Result<Mask, ParseError> {
+    if width < text.len() as u32 {
+        return Err(ParseError::MaskTooLong {
+            length: text.len(),
+            width,
+        });
+    }
+    let mut bitwise_and = 0;
+    let mut bitwise_or = 0;
+    for c in text.chars() {
+        bitwise_and <<= 1;
+        bitwise_or <<= 1;
+        match c {
+            '0' => {
+            },
+            '1' => {
+                bitwise_and |= 1;
+                bitwise_or |= 1;
+            },
+            'X' => {
+                bitwise_and |= 1;
             },
+            _ => return Err(ParseError::IllegalMaskCharacter(c)),
+        }
+    }
+    Ok(Mask {
+        bitwise_and,
+        bitwise_or,
+    })
+}
+
+fn parse_word(text: &str, width: u32) -> Result<Word, ParseError> {
+    let too_wide = || ParseError::NumberTooWide {
+        number: text.to_owned(),
+        width,
+    };
+    let word: Word = text.parse().map_err(|_| too_wide())?;
+    if word & !width_mask(width) != 0 {
+        return Err(too_wide());
+    }
+    Ok(word)
+}
+
+pub fn parse(input: &str, width: u32) -> Result<Program, ParseError> {
+    assert!(0 < width && width <= MAX_WIDTH, "Word width must be between 1 and {}", MAX_WIDTH);
+    let pattern = regex::Regex::new(r"^(?:mask|mem\[(\d+)\]) = (.*)$").unwrap();
+    let instructions = input.lines().map(|line| {
+        let m = pattern.captures(line).ok_or_else(|| ParseError::MalformedLine(line.to_owned()))?;
+        Ok(match m.get(1) {
+            None => Instruction::Mask(parse_mask(&m[2], width)?),
             Some(address) => {
                 Instruction::Memory {
-                    address: address.as_str().parse().unwrap(),
-                    value: m[2].parse().unwrap(),
+                    address: parse_word(address.as_str(), width)?,
+                    value: parse_word(&m[2], width)?,
                 }
             },
-        }
-    }).collect()
+        })
+    }).collect::<Result<_, _>>()?;
+    Ok(Program {
+        width,
+        instructions,
+    })
 }
 
-struct ComputerState {
-    mask: Mask,
-    memory: HashMap<usize, usize>,
+pub fn generator(input: &str) -> Program {
+    parse(input, 36).unwrap_or_else(|err| panic!("{}", err))
 }
 
 impl Mask {
-    fn apply(&self, value: usize) -> usize {
-        (value & self.bitwise_and) | self.bitwise_or
+    pub fn identity(width: u32) -> Mask {
+        Mask {
+            bitwise_and: width_mask(width),
+            bitwise_or: 0,
+        }
     }
 
-    fn all_floating_submasks(&self) -> impl Iterator<Item = usize> {
-        let places_of_difference = self.bitwise_and ^ self.bitwise_or;
-        std::iter::successors(Some((places_of_difference, 1)), |&(places_of_difference, curr_mask)| {
-            if places_of_difference == 0 {
-                return None;
-            }
-            let new_places_of_difference = places_of_difference >> 1;
-            let new_curr_mask = curr_mask << 1;
-            Some((new_places_of_difference, new_curr_mask))
-        }).filter_map(|(places_of_difference, curr_mask)| {
-            if places_of_difference % 2 == 1 {
-                Some(curr_mask)
-            } else {
-                None
-            }
-        })
+    pub fn ones(&self) -> Word {
+        self.bitwise_or
     }
 
-    fn all_floating_possibilities(&self, address: usize) -> impl Iterator<Item = usize> {
-        let address = address | self.bitwise_or;
-        let floating_submasks: Vec<_> = self.all_floating_submasks().collect();
-        (0..(1 << floating_submasks.len())).map(move |mut choice| {
-            let mut address = address;
-            for submask in floating_submasks.iter() {
-                address |= submask;
-                if choice % 2 == 0 {
-                    address -= submask;
-                }
-                choice >>= 1;
-            }
-            address
-        })
+    pub fn floating(&self) -> Word {
+        self.bitwise_and ^ self.bitwise_or
+    }
+
+    pub fn apply(&self, value: Word) -> Word {
+        (value & self.bitwise_and) | self.bitwise_or
+    }
+
+    pub fn floating_address(&self, address: Word) -> AddressPattern {
+        AddressPattern::new(address | self.bitwise_or, self.floating())
     }
 }
 
-pub fn part_1(input: &[Instruction]) -> usize {
-    let mut state = ComputerState {
-        mask: Mask {
-            bitwise_and: (1 << 36) - 1,
-            bitwise_or: 0,
-        },
-        memory: HashMap::default(),
-    };
-    for instruction in input.iter() {
-        match instruction {
-            Instruction::Mask(mask) => {
-                state.mask = mask.clone();
-            },
-            Instruction::Memory { address, value } => {
-                state.memory.insert(*address, state.mask.apply(*value));
-            },
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressPattern {
+    floating: Word,
+    fixed: Word,
+}
+
+impl AddressPattern {
+    pub fn new(address: Word, floating: Word) -> AddressPattern {
+        AddressPattern {
+            floating,
+            fixed: address & !floating,
         }
     }
-    state.memory.values().sum()
+
+    pub fn exact(address: Word) -> AddressPattern {
+        AddressPattern::new(address, 0)
+    }
+
+    pub fn lowest_address(&self) -> Word {
+        self.fixed
+    }
+
+    pub fn floating(&self) -> Word {
+        self.floating
+    }
+
+    pub fn size(&self) -> num::BigUint {
+        num::BigUint::from(1u8) << self.floating.count_ones() as usize
+    }
+
+    fn intersects(&self, other: &AddressPattern) -> bool {
+        let fixed_in_both = !self.floating & !other.floating;
+        (self.fixed ^ other.fixed) & fixed_in_both == 0
+    }
+
+    fn subtract(&self, other: &AddressPattern) -> Vec<AddressPattern> {
+        if !self.intersects(other) {
+            return vec![*self];
+        }
+        let mut result = Vec::new();
+        let mut remainder = *self;
+        let mut bits_to_split = self.floating & !other.floating;
+        while bits_to_split != 0 {
+            let bit = bits_to_split & bits_to_split.wrapping_neg();
+            bits_to_split ^= bit;
+            remainder.floating ^= bit;
+            // The part that disagrees with `other` on this bit is outside of it...
+            result.push(AddressPattern {
+                floating: remainder.floating,
+                fixed: remainder.fixed | (!other.fixed & bit),
+            });
+            // ...and the part that agrees is what we keep splitting.
+            remainder.fixed |= other.fixed & bit;
+        }
+        result
+    }
 }
 
-pub fn part_2(input: &[Instruction]) -> usize {
-    let mut state = ComputerState {
-        mask: Mask {
-            bitwise_and: (1 << 36) - 1,
-            bitwise_or: 0,
-        },
-        memory: HashMap::default(),
-    };
-    for instruction in input.iter() {
+pub trait MaskDecoder {
+    fn decode(&self, mask: &Mask, address: Word, value: Word) -> (AddressPattern, Word);
+}
+
+impl<F: Fn(&Mask, Word, Word) -> (AddressPattern, Word)> MaskDecoder for F {
+    fn decode(&self, mask: &Mask, address: Word, value: Word) -> (AddressPattern, Word) {
+        self(mask, address, value)
+    }
+}
+
+pub struct ValueMasking;
+
+impl MaskDecoder for ValueMasking {
+    fn decode(&self, mask: &Mask, address: Word, value: Word) -> (AddressPattern, Word) {
+        (AddressPattern::exact(address), mask.apply(value))
+    }
+}
+
+pub struct AddressFloating;
+
+impl MaskDecoder for AddressFloating {
+    fn decode(&self, mask: &Mask, address: Word, value: Word) -> (AddressPattern, Word) {
+        (mask.floating_address(address), value)
+    }
+}
+
+pub struct DockingComputer<D: MaskDecoder> {
+    decoder: D,
+    mask: Mask,
+    writes: Vec<(AddressPattern, Word)>,
+}
+
+impl<D: MaskDecoder> DockingComputer<D> {
+    pub fn new(width: u32, decoder: D) -> Self {
+        assert!(0 < width && width <= MAX_WIDTH, "Word width must be between 1 and {}", MAX_WIDTH);
+        Self {
+            decoder,
+            mask: Mask::identity(width),
+            writes: Vec::new(),
+        }
+    }
+
+    pub fn for_program(program: &Program, decoder: D) -> Self {
+        let mut computer = Self::new(program.width, decoder);
+        computer.run(&program.instructions);
+        computer
+    }
+
+    pub fn execute(&mut self, instruction: &Instruction) {
         match instruction {
             Instruction::Mask(mask) => {
-                state.mask = mask.clone();
+                self.mask = mask.clone();
             },
             Instruction::Memory { address, value } => {
-                for floating_address in state.mask.all_floating_possibilities(*address) {
-                    state.memory.insert(floating_address, *value);
-                }
+                let (pattern, value) = self.decoder.decode(&self.mask, *address, *value);
+                self.write(pattern, value);
             },
         }
     }
-    state.memory.values().sum()
+
+    pub fn run(&mut self, instructions: &[Instruction]) {
+        for instruction in instructions.iter() {
+            self.execute(instruction);
+        }
+    }
+
+    fn write(&mut self, pattern: AddressPattern, value: Word) {
+        self.writes = self.writes.drain(..).flat_map(|(old_pattern, old_value)| {
+            old_pattern.subtract(&pattern).into_iter().map(move |piece| (piece, old_value))
+        }).collect();
+        if value != 0 {
+            self.writes.push((pattern, value));
+        }
+    }
+
+    pub fn memory_sum(&self) -> num::BigUint {
+        self.writes.iter().map(|(pattern, value)| pattern.size() * value).sum()
+    }
+
+    pub fn memory_dump(&self) -> Vec<(AddressPattern, Word)> {
+        let mut dump = self.writes.clone();
+        dump.sort_by_key(|(pattern, _)| (pattern.lowest_address(), pattern.floating()));
+        dump
+    }
+}
+
+pub fn part_1(input: &Program) -> num::BigUint {
+    DockingComputer::for_program(input, ValueMasking).memory_sum()
+}
+
+pub fn part_2(input: &Program) -> num::BigUint {
+    DockingComputer::for_program(input, AddressFloating).memory_sum()
 }
@@ -1,61 +1,96 @@
-use std::num::NonZeroUsize;
+use hashbrown::HashMap;
 
-pub fn generator(input: &str) -> Vec<usize> {
+pub fn generator(input: &str) -> Vec<u32> {
     input.split(',').map(|line| line.parse().unwrap()).collect()
 }
 
+// Numbers below `rounds / DENSE_FRACTION` are kept in a flat table, and the rest in a hash map.
+// The map is not small - over 30M rounds about 1.44M of the 3.61M distinct numbers (40%) go there -
+// but a table for all of them would take 120MB while this split takes about 35MB, and the run time
+// stayed within noise for fractions 1 through 8.
+const DENSE_FRACTION: u32 = 8;
+
+const PROGRESS_INTERVAL: u32 = 1 << 20;
+
 #[derive(Debug)]
-struct Game {
-    last_round: usize,
-    next_number: usize,
-    last_seen_times: Vec<Option<NonZeroUsize>>
+pub struct Game {
+    last_round: u32,
+    next_number: u32,
+    // 0 means never seen - rounds start from 1
+    dense_last_seen_rounds: Vec<u32>,
+    sparse_last_seen_rounds: HashMap<u32, u32>,
 }
 
 impl Game {
-    fn new() -> Self {
+    pub fn new(rounds: u32) -> Self {
         Self {
             last_round: 0,
             next_number: 0,
-            last_seen_times: Vec::new(),
+            dense_last_seen_rounds: vec![0; (rounds / DENSE_FRACTION).max(1) as usize],
+            sparse_last_seen_rounds: HashMap::new(),
         }
     }
 
-    fn feed_number(&mut self, number: usize) {
-        self.last_round += 1;
-        if self.last_seen_times.len() <= number {
-            self.last_seen_times.resize(number + 1, None);
-        }
-        self.next_number = if let Some(last_seen_time) = self.last_seen_times[number] {
-            self.last_round - last_seen_time.get()
+    fn replace_last_seen_round(&mut self, number: u32, round: u32) -> u32 {
+        if let Some(slot) = self.dense_last_seen_rounds.get_mut(number as usize) {
+            std::mem::replace(slot, round)
         } else {
+            self.sparse_last_seen_rounds.insert(number, round).unwrap_or(0)
+        }
+    }
+
+    pub fn feed_number(&mut self, number: u32) {
+        self.last_round += 1;
+        let last_seen_round = self.replace_last_seen_round(number, self.last_round);
+        self.next_number = if last_seen_round == 0 {
             0
+        } else {
+            self.last_round - last_seen_round
         };
-        self.last_seen_times[number] = NonZeroUsize::new(self.last_round);
     }
 }
 
 impl Iterator for Game {
-    type Item = usize;
+    type Item = u32;
 
-    fn next(&mut self) -> Option<usize> {
+    fn next(&mut self) -> Option<u32> {
         let result = self.next_number;
         self.feed_number(self.next_number);
         Some(result)
     }
 }
 
-pub fn part_1(input: &[usize]) -> usize {
-    let mut game = Game::new();
-    for number in input.iter() {
-        game.feed_number(*number);
+pub fn numbers_at_rounds(starting_numbers: &[u32], rounds: &[u32], mut progress: impl FnMut(u32, u32)) -> Vec<u32> {
+    assert!(rounds.iter().all(|&round| 1 <= round), "Rounds are counted from 1");
+    let mut queries: Vec<(u32, usize)> = rounds.iter().enumerate().map(|(i, &round)| (round, i)).collect();
+    queries.sort();
+    let last_round = queries.last().map_or(0, |&(round, _)| round);
+    let mut results = vec![0; rounds.len()];
+    let mut pending_queries = queries.into_iter().peekable();
+
+    let mut game = Game::new(last_round);
+    for round in 1..=last_round {
+        let number = if let Some(&number) = starting_numbers.get(round as usize - 1) {
+            number
+        } else {
+            game.next_number
+        };
+        while let Some((_, i)) = pending_queries.next_if(|&(query_round, _)| query_round == round) {
+            results[i] = number;
+        }
+        game.feed_number(number);
+        if round % PROGRESS_INTERVAL == 0 {
+            progress(round, last_round);
+        }
     }
-    game.nth(2020 - input.len() - 1).unwrap()
+    progress(last_round, last_round);
+    results
 }
 
-pub fn part_2(input: &[usize]) -> usize {
-    let mut game = Game::new();
-    for number in input.iter() {
-        game.feed_number(*number);
-    }
-    game.nth(30000000 - input.len() - 1).unwrap()
+pub fn part_1(input: &[u32]) -> u32 {
+    numbers_at_rounds(input, &[2020], |_, _| {})[0]
+}
+
+pub fn part_2(input: &[u32]) -> u32 {
+    numbers_at_rounds(input, &[30000000], |_, _| {})[0]
 }
@@ -1,10 +1,10 @@
+use std::collections::BTreeMap;
 use std::ops::RangeInclusive;
 
 #[derive(Debug)]
 pub struct Field {
     name: String,
     ranges: Vec<RangeInclusive<usize>>,
-    mask: usize,
 }
 
 #[derive(Debug)]
@@ -26,7 +26,7 @@ impl From<&str> for Ticket {
 pub fn generator(input: &str) -> Input {
     let mut it = input.lines();
 
-    let fields = it.by_ref().take_while(|&line| line != "").enumerate().map(|(idx, line)| {
+    let fields = it.by_ref().take_while(|&line| line != "").map(|line| {
         let mut parts = line.split(": ");
 
         let name = parts.next().unwrap().to_owned();
@@ -40,9 +40,7 @@ pub fn generator(input: &str) -> Input {
 
         assert!(parts.next() == None);
 
-        let mask = 1 << idx;
-
-        Field {name, ranges, mask}
+        Field {name, ranges: merge_ranges(ranges)}
     }).collect();
 
     assert!(it.next() == Some("your ticket:"));
@@ -56,59 +54,346 @@ pub fn generator(input: &str) -> Input {
     Input {fields, my_ticket, nearby_tickets}
 }
 
+fn merge_ranges(mut ranges: Vec<RangeInclusive<usize>>) -> Vec<RangeInclusive<usize>> {
+    ranges.sort_by_key(|range| *range.start());
+    let mut merged: Vec<RangeInclusive<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        if let Some(last) = merged.last_mut() {
+            if *range.start() <= last.end() + 1 {
+                if last.end() < range.end() {
+                    *last = *last.start()..=*range.end();
+                }
+                continue;
+            }
+        }
+        merged.push(range);
+    }
+    merged
+}
+
 impl Field {
+    fn range_index(&self, number: usize) -> Option<usize> {
+        let index = self.ranges.partition_point(|range| *range.end() < number);
+        if self.ranges.get(index)?.contains(&number) {
+            Some(index)
+        } else {
+            None
+        }
+    }
+
     fn is_in_range(&self, number: usize) -> bool {
-        self.ranges.iter().any(|range| range.contains(&number))
+        self.range_index(number).is_some()
     }
 }
 
-impl Ticket {
-    fn is_valid(&self, input: &Input) -> bool {
-        self.0.iter().all(|&number| input.fields.iter().any(|field| field.is_in_range(number)))
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitSet {
+    len: usize,
+    words: Vec<u64>,
+}
+
+impl BitSet {
+    pub fn empty(len: usize) -> Self {
+        Self {
+            len,
+            words: vec![0; len.div_ceil(64)],
+        }
+    }
+
+    pub fn full(len: usize) -> Self {
+        let mut result = Self::empty(len);
+        for i in 0..len {
+            result.insert(i);
+        }
+        result
+    }
+
+    pub fn insert(&mut self, i: usize) {
+        self.words[i / 64] |= 1 << (i % 64);
+    }
+
+    pub fn remove(&mut self, i: usize) {
+        self.words[i / 64] &= !(1 << (i % 64));
+    }
+
+    pub fn contains(&self, i: usize) -> bool {
+        i < self.len && self.words[i / 64] & (1 << (i % 64)) != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub fn single(&self) -> Option<usize> {
+        if self.count() == 1 {
+            self.iter().next()
+        } else {
+            None
+        }
+    }
+
+    pub fn intersect_with(&mut self, other: &BitSet) {
+        for (word, other_word) in self.words.iter_mut().zip(other.words.iter()) {
+            *word &= other_word;
+        }
+    }
+
+    pub fn iter(&self) -> impl '_ + Iterator<Item = usize> {
+        (0..self.len).filter(move |&i| self.contains(i))
     }
 }
 
-pub fn part_1(input: &Input) -> usize {
-    input.nearby_tickets.iter()
-        .flat_map(|ticket| ticket.0.iter())
-        .filter(|&number| !input.fields.iter().any(|field| field.is_in_range(*number)))
-        .sum()
+#[derive(Debug)]
+pub struct ValueScan {
+    pub value: usize,
+    pub compatible_fields: BitSet,
 }
 
-pub fn part_2(input: &Input) -> usize {
-    let mut slots_masks = vec![(1 << input.fields.len()) - 1; input.fields.len()];
-    let valid_tickets = input.nearby_tickets.iter().filter(|t| t.is_valid(input)).collect::<Vec<_>>();
-    for ticket in valid_tickets {
-        for (value, slot_mask) in ticket.0.iter().zip(slots_masks.iter_mut()) {
-            for field in input.fields.iter() {
-                if !field.is_in_range(*value) {
-                    *slot_mask &= !field.mask;
+#[derive(Debug)]
+pub struct TicketScan(Vec<ValueScan>);
+
+impl TicketScan {
+    pub fn values(&self) -> &[ValueScan] {
+        &self.0
+    }
+
+    pub fn invalid_values(&self) -> impl '_ + Iterator<Item = usize> {
+        self.0.iter().filter(|scan| scan.compatible_fields.count() == 0).map(|scan| scan.value)
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.invalid_values().next().is_none()
+    }
+}
+
+#[derive(Debug)]
+pub struct RangeTightness {
+    pub range: RangeInclusive<usize>,
+    pub observed: Option<RangeInclusive<usize>>,
+}
+
+impl RangeTightness {
+    // How much of the range, below and above, no ticket value has used
+    pub fn slack(&self) -> Option<(usize, usize)> {
+        let observed = self.observed.as_ref()?;
+        Some((observed.start() - self.range.start(), self.range.end() - observed.end()))
+    }
+}
+
+#[derive(Debug)]
+pub struct FieldStats {
+    pub histogram: BTreeMap<usize, usize>,
+    pub ranges: Vec<RangeTightness>,
+}
+
+#[derive(Debug)]
+pub struct ScanReport {
+    pub tickets: Vec<TicketScan>,
+    pub fields: Vec<FieldStats>,
+}
+
+impl Ticket {
+    pub fn scan(&self, fields: &[Field]) -> TicketScan {
+        TicketScan(self.0.iter().map(|&value| {
+            let mut compatible_fields = BitSet::empty(fields.len());
+            for (field_index, field) in fields.iter().enumerate() {
+                if field.is_in_range(value) {
+                    compatible_fields.insert(field_index);
                 }
             }
+            ValueScan {value, compatible_fields}
+        }).collect())
+    }
+}
+
+pub fn scan(input: &Input) -> ScanReport {
+    let tickets: Vec<TicketScan> = input.nearby_tickets.iter().map(|ticket| ticket.scan(&input.fields)).collect();
+
+    let mut fields: Vec<FieldStats> = input.fields.iter().map(|field| {
+        FieldStats {
+            histogram: BTreeMap::new(),
+            ranges: field.ranges.iter().map(|range| RangeTightness {
+                range: range.clone(),
+                observed: None,
+            }).collect(),
+        }
+    }).collect();
+
+    for ticket_scan in tickets.iter().filter(|ticket_scan| ticket_scan.is_valid()) {
+        for value_scan in ticket_scan.values() {
+            for field_index in value_scan.compatible_fields.iter() {
+                let stats = &mut fields[field_index];
+                *stats.histogram.entry(value_scan.value).or_insert(0) += 1;
+                let range_index = input.fields[field_index].range_index(value_scan.value).unwrap();
+                let observed = &mut stats.ranges[range_index].observed;
+                *observed = Some(match observed.take() {
+                    None => value_scan.value..=value_scan.value,
+                    Some(observed) => *observed.start().min(&value_scan.value)..=*observed.end().max(&value_scan.value),
+                });
+            }
         }
     }
 
-    let mut verified_slots = vec![None; slots_masks.len()];
-    loop {
-        if let Some(next_verified) = slots_masks.iter().zip(verified_slots.iter())
-            .position(|(mask, &verified)| verified == None && mask.count_ones() == 1)
-        {
-            let field_index = slots_masks[next_verified].trailing_zeros() as usize;
-            let field = &input.fields[field_index];
-            verified_slots[next_verified] = Some(field.name.as_str());
-            assert!(slots_masks[next_verified] == field.mask);
-            for (i, slot_mask) in slots_masks.iter_mut().enumerate() {
-                if i != next_verified {
-                    *slot_mask &= !field.mask;
+    ScanReport {tickets, fields}
+}
+
+impl ScanReport {
+    pub fn error_rate(&self) -> usize {
+        self.tickets.iter().flat_map(|ticket_scan| ticket_scan.invalid_values()).sum()
+    }
+
+    pub fn describe(&self, input: &Input) -> String {
+        use std::fmt::Write;
+
+        let mut result = String::new();
+        for (ticket_index, ticket_scan) in self.tickets.iter().enumerate() {
+            writeln!(&mut result, "Nearby ticket {}:", ticket_index).unwrap();
+            for value_scan in ticket_scan.values() {
+                if value_scan.compatible_fields.count() == 0 {
+                    writeln!(&mut result, "    {} - matches no field", value_scan.value).unwrap();
+                } else {
+                    let names: Vec<_> = value_scan.compatible_fields.iter().map(|field_index| input.fields[field_index].name.as_str()).collect();
+                    writeln!(&mut result, "    {} - {}", value_scan.value, names.join(", ")).unwrap();
                 }
             }
+        }
+        for (field, stats) in input.fields.iter().zip(self.fields.iter()) {
+            let values_count: usize = stats.histogram.values().sum();
+            writeln!(&mut result, "Field {:?}: {} values, {} distinct", field.name, values_count, stats.histogram.len()).unwrap();
+            for range_tightness in stats.ranges.iter() {
+                match range_tightness.slack() {
+                    None => writeln!(&mut result, "    {:?} - unused", range_tightness.range).unwrap(),
+                    Some((below, above)) => writeln!(&mut result, "    {:?} - slack {} below, {} above", range_tightness.range, below, above).unwrap(),
+                }
+            }
+        }
+        result
+    }
+}
+
+pub fn part_1(input: &Input) -> usize {
+    scan(input).error_rate()
+}
+
+#[derive(Debug)]
+pub enum FieldDeduction {
+    Unique(Vec<usize>),
+    Ambiguous(Vec<BitSet>),
+    Unsatisfiable,
+}
+
+pub fn slot_candidates(input: &Input) -> Vec<BitSet> {
+    let mut candidates = vec![BitSet::full(input.fields.len()); input.my_ticket.0.len()];
+    for ticket_scan in scan(input).tickets.iter().filter(|ticket_scan| ticket_scan.is_valid()) {
+        for (value_scan, slot_candidates) in ticket_scan.values().iter().zip(candidates.iter_mut()) {
+            slot_candidates.intersect_with(&value_scan.compatible_fields);
+        }
+    }
+    candidates
+}
+
+fn find_matching(candidates: &[BitSet], num_fields: usize) -> Option<Vec<usize>> {
+    fn try_augment(slot: usize, candidates: &[BitSet], visited: &mut BitSet, field_owners: &mut [Option<usize>]) -> bool {
+        for field in candidates[slot].iter() {
+            if visited.contains(field) {
+                continue;
+            }
+            visited.insert(field);
+            let can_take = match field_owners[field] {
+                None => true,
+                Some(owner) => try_augment(owner, candidates, visited, field_owners),
+            };
+            if can_take {
+                field_owners[field] = Some(slot);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut field_owners = vec![None; num_fields];
+    for slot in 0..candidates.len() {
+        if !try_augment(slot, candidates, &mut BitSet::empty(num_fields), &mut field_owners) {
+            return None;
+        }
+    }
+    let mut assignment = vec![0; candidates.len()];
+    for (field, owner) in field_owners.into_iter().enumerate() {
+        if let Some(slot) = owner {
+            assignment[slot] = field;
+        }
+    }
+    Some(assignment)
+}
+
+fn fix_slot(candidates: &[BitSet], slot: usize, field: usize) -> Vec<BitSet> {
+    let mut result = candidates.to_vec();
+    for (i, slot_candidates) in result.iter_mut().enumerate() {
+        if i == slot {
+            *slot_candidates = BitSet::empty(slot_candidates.len);
+            slot_candidates.insert(field);
         } else {
-            break;
+            slot_candidates.remove(field);
         }
     }
+    result
+}
+
+pub fn deduce_fields(candidates: &[BitSet], num_fields: usize) -> FieldDeduction {
+    let matching = if let Some(matching) = find_matching(candidates, num_fields) {
+        matching
+    } else {
+        return FieldDeduction::Unsatisfiable;
+    };
+    let possible: Vec<BitSet> = candidates.iter().enumerate().map(|(slot, slot_candidates)| {
+        let mut possible = BitSet::empty(num_fields);
+        for field in slot_candidates.iter() {
+            if field == matching[slot] || find_matching(&fix_slot(candidates, slot, field), num_fields).is_some() {
+                possible.insert(field);
+            }
+        }
+        possible
+    }).collect();
+    if possible.iter().all(|slot_possible| slot_possible.count() == 1) {
+        FieldDeduction::Unique(matching)
+    } else {
+        FieldDeduction::Ambiguous(possible)
+    }
+}
+
+pub fn all_assignments(candidates: &[BitSet], num_fields: usize) -> Vec<Vec<usize>> {
+    fn search(candidates: &[BitSet], num_fields: usize, slot: usize, assignment: &mut Vec<usize>, result: &mut Vec<Vec<usize>>) {
+        if slot == candidates.len() {
+            result.push(assignment.clone());
+            return;
+        }
+        for field in candidates[slot].iter() {
+            let fixed = fix_slot(candidates, slot, field);
+            if find_matching(&fixed, num_fields).is_some() {
+                assignment.push(field);
+                search(&fixed, num_fields, slot + 1, assignment, result);
+                assignment.pop();
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+    search(candidates, num_fields, 0, &mut Vec::new(), &mut result);
+    result
+}
+
+pub fn part_2(input: &Input) -> usize {
+    let candidates = slot_candidates(input);
+    let assignment = match deduce_fields(&candidates, input.fields.len()) {
+        FieldDeduction::Unique(assignment) => assignment,
+        FieldDeduction::Ambiguous(possible) => {
+            let undetermined = possible.iter().filter(|slot_possible| 1 < slot_possible.count()).count();
+            panic!("Ticket fields are ambiguous - {} slots can match more than one field", undetermined);
+        },
+        FieldDeduction::Unsatisfiable => panic!("No assignment of fields to slots fits the nearby tickets"),
+    };
 
-    let fields_by_slot = verified_slots.into_iter().map(Option::unwrap);
-    fields_by_slot.zip(input.my_ticket.0.iter())
+    assignment.into_iter().map(|field_index| input.fields[field_index].name.as_str()).zip(input.my_ticket.0.iter())
         .filter_map(|(field_name, value)| {
             if field_name.starts_with("departure") {
                 Some(value)
@@ -1,8 +1,32 @@
-#[derive(Debug, Clone, Copy)]
+use std::convert::TryFrom;
+
+use hashbrown::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+impl core::fmt::Display for Operator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operator::Add => write!(f, "+"),
+            Operator::Subtract => write!(f, "-"),
+            Operator::Multiply => write!(f, "*"),
+            Operator::Divide => write!(f, "/"),
+            Operator::Power => write!(f, "^"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Token {
-    Number(usize),
-    Plus,
-    Times,
+    Number(String),
+    Operator(Operator),
     Open,
     Close,
 }
@@ -11,8 +35,7 @@ impl core::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Token::Number(num) => write!(f, "{}", num),
-            Token::Plus => write!(f, "+"),
-            Token::Times => write!(f, "*"),
+            Token::Operator(operator) => write!(f, "{}", operator),
             Token::Open => write!(f, "("),
             Token::Close => write!(f, ")"),
         }
@@ -20,176 +43,425 @@ impl core::fmt::Display for Token {
 }
 
 #[derive(Debug)]
-pub struct Equation(Vec<Token>);
+pub struct Equation {
+    source: String,
+    tokens: Vec<(usize, Token)>,
+}
 
 impl core::fmt::Display for Equation {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for token in self.0.iter() {
+        for (_, token) in self.tokens.iter() {
             write!(f, "{}", token)?;
         }
         Ok(())
     }
 }
 
+fn caret_diagram(line: &str, position: usize, message: impl core::fmt::Display) -> String {
+    let column = line[..position.min(line.len())].chars().count();
+    format!("{}\n{}^ {}", line, " ".repeat(column), message)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntaxErrorKind {
+    UnknownCharacter(char),
+    UnclosedParenthesis,
+    UnmatchedClosingParenthesis,
+    MissingOperand,
+    MissingOperator,
+    TrailingOperator,
+    UnsupportedOperator(Operator),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntaxError {
+    pub kind: SyntaxErrorKind,
+    pub position: usize,
+}
+
+impl SyntaxError {
+    fn message(&self) -> String {
+        match self.kind {
+            SyntaxErrorKind::UnknownCharacter(c) => format!("unknown character {:?}", c),
+            SyntaxErrorKind::UnclosedParenthesis => "unclosed parenthesis".to_owned(),
+            SyntaxErrorKind::UnmatchedClosingParenthesis => "unmatched closing parenthesis".to_owned(),
+            SyntaxErrorKind::MissingOperand => "missing operand".to_owned(),
+            SyntaxErrorKind::MissingOperator => "missing operator".to_owned(),
+            SyntaxErrorKind::TrailingOperator => "operator has no right operand".to_owned(),
+            SyntaxErrorKind::UnsupportedOperator(operator) => format!("operator {} is not supported", operator),
+        }
+    }
+
+    pub fn render(&self, line: &str) -> String {
+        caret_diagram(line, self.position, self.message())
+    }
+}
+
+impl core::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message(), self.position)
+    }
+}
+
+impl Equation {
+    pub fn tokenize(line: &str) -> Result<Self, SyntaxError> {
+        let mut tokens = Vec::new();
+        let mut chars = line.char_indices().peekable();
+        while let Some((position, c)) = chars.next() {
+            let token = match c {
+                '+' => Token::Operator(Operator::Add),
+                '-' => Token::Operator(Operator::Subtract),
+                '*' => Token::Operator(Operator::Multiply),
+                '/' => Token::Operator(Operator::Divide),
+                '^' => Token::Operator(Operator::Power),
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '0'..='9' => {
+                    let mut end = position + 1;
+                    while let Some((digit_position, _)) = chars.next_if(|(_, c)| c.is_ascii_digit()) {
+                        end = digit_position + 1;
+                    }
+                    Token::Number(line[position..end].to_owned())
+                },
+                c if c.is_whitespace() => continue,
+                c => return Err(SyntaxError {
+                    kind: SyntaxErrorKind::UnknownCharacter(c),
+                    position,
+                }),
+            };
+            tokens.push((position, token));
+        }
+        Ok(Self {
+            source: line.to_owned(),
+            tokens,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+}
+
 pub fn generator(equations: &str) -> Vec<Equation> {
-    let pattern = regex::Regex::new(r"\d+|[+*()]").unwrap();
     equations.lines().map(|line| {
-        Equation(
-        pattern.find_iter(line).map(|m| match m.as_str() {
-            "+" => Token::Plus,
-            "*" => Token::Times,
-            "(" => Token::Open,
-            ")" => Token::Close,
-            num => Token::Number(num.parse().unwrap()),
-        }).collect())
+        Equation::tokenize(line).unwrap_or_else(|err| panic!("\n{}", err.render(line)))
     }).collect()
 }
 
-fn solve_1(tokens: &mut dyn Iterator<Item = Token>) -> usize {
-    #[derive(Debug)]
-    enum State {
-        Number(usize),
-        Add(usize),
-        Multiply(usize),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Associativity {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct OperatorTable(HashMap<Operator, (u8, Associativity)>);
+
+impl OperatorTable {
+    pub fn with(mut self, operator: Operator, precedence: u8, associativity: Associativity) -> Self {
+        self.0.insert(operator, (precedence, associativity));
+        self
     }
-    impl State {
-        fn apply(&mut self, right: usize) {
-            *self = match self {
-                State::Number(_) => panic!("Illegal application while {:?}", self),
-                State::Add(left) => State::Number(*left + right),
-                State::Multiply(left) => State::Number(*left * right),
-            };
-        }
+
+    pub fn left_to_right() -> Self {
+        Self::default()
+            .with(Operator::Add, 1, Associativity::Left)
+            .with(Operator::Subtract, 1, Associativity::Left)
+            .with(Operator::Multiply, 1, Associativity::Left)
+            .with(Operator::Divide, 1, Associativity::Left)
+            .with(Operator::Power, 1, Associativity::Left)
     }
-    let mut state = State::Add(0);
-    while let Some(token) = tokens.next() {
-        match token {
-            Token::Number(num) => {
-                state.apply(num);
-            },
-            Token::Plus => {
-                match state {
-                    State::Number(num) => {
-                        state = State::Add(num);
-                    }
-                    _ => panic!("Illegal {:?} while {:?}", token, state),
-                }
-            }
-            Token::Times => {
-                match state {
-                    State::Number(num) => {
-                        state = State::Multiply(num);
-                    }
-                    _ => panic!("Illegal {:?} while {:?}", token, state),
+
+    pub fn addition_first() -> Self {
+        Self::default()
+            .with(Operator::Multiply, 1, Associativity::Left)
+            .with(Operator::Divide, 1, Associativity::Left)
+            .with(Operator::Add, 2, Associativity::Left)
+            .with(Operator::Subtract, 2, Associativity::Left)
+            .with(Operator::Power, 3, Associativity::Right)
+    }
+
+    fn get(&self, operator: Operator) -> (u8, Associativity) {
+        *self.0.get(&operator).unwrap_or_else(|| panic!("Operator {} is not in the table", operator))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Number {
+        literal: String,
+        position: usize,
+    },
+    BinaryOp {
+        operator: Operator,
+        position: usize,
+        left: Box<Expr>,
+        right: Box<Expr>,
+    },
+}
+
+struct Parser<'a> {
+    tokens: std::iter::Peekable<std::slice::Iter<'a, (usize, Token)>>,
+    end: usize,
+    table: &'a OperatorTable,
+}
+
+impl Parser<'_> {
+    fn error<T>(kind: SyntaxErrorKind, position: usize) -> Result<T, SyntaxError> {
+        Err(SyntaxError {kind, position})
+    }
+
+    fn parse_primary(&mut self, after_operator: Option<usize>) -> Result<Expr, SyntaxError> {
+        match self.tokens.next() {
+            Some((position, Token::Number(num))) => Ok(Expr::Number {
+                literal: num.clone(),
+                position: *position,
+            }),
+            Some((open_position, Token::Open)) => {
+                let expr = self.parse_expr(0, None)?;
+                match self.tokens.next() {
+                    Some((_, Token::Close)) => Ok(expr),
+                    None => Self::error(SyntaxErrorKind::UnclosedParenthesis, *open_position),
+                    Some((position, _)) => Self::error(SyntaxErrorKind::MissingOperator, *position),
                 }
-            }
-            Token::Open => {
-                state.apply(solve_1(tokens));
-            }
-            Token::Close => {
+            },
+            Some((position, _)) => Self::error(SyntaxErrorKind::MissingOperand, *position),
+            None => match after_operator {
+                Some(operator_position) => Self::error(SyntaxErrorKind::TrailingOperator, operator_position),
+                None => Self::error(SyntaxErrorKind::MissingOperand, self.end),
+            },
+        }
+    }
+
+    fn parse_expr(&mut self, min_precedence: u8, after_operator: Option<usize>) -> Result<Expr, SyntaxError> {
+        let mut left = self.parse_primary(after_operator)?;
+        while let Some((position, Token::Operator(operator))) = self.tokens.peek() {
+            let (position, operator) = (*position, *operator);
+            let (precedence, associativity) = if let Some(entry) = self.table.0.get(&operator) {
+                *entry
+            } else {
+                return Self::error(SyntaxErrorKind::UnsupportedOperator(operator), position);
+            };
+            if precedence < min_precedence {
                 break;
             }
+            self.tokens.next();
+            let right = self.parse_expr(match associativity {
+                Associativity::Left => precedence + 1,
+                Associativity::Right => precedence,
+            }, Some(position))?;
+            left = Expr::BinaryOp {
+                operator,
+                position,
+                left: Box::new(left),
+                right: Box::new(right),
+            };
         }
-    }
-    if let State::Number(result) = state {
-        result
-    } else {
-        panic!("Unfinished resolution at {:?}", state);
+        Ok(left)
     }
 }
 
 impl Equation {
-    fn solve_1(&self) -> usize {
-        solve_1(&mut self.0.iter().copied())
+    pub fn parse(&self, table: &OperatorTable) -> Result<Expr, SyntaxError> {
+        let mut parser = Parser {
+            tokens: self.tokens.iter().peekable(),
+            end: self.source.len(),
+            table,
+        };
+        let expr = parser.parse_expr(0, None)?;
+        match parser.tokens.next() {
+            None => Ok(expr),
+            Some((position, Token::Close)) => Parser::error(SyntaxErrorKind::UnmatchedClosingParenthesis, *position),
+            Some((position, _)) => Parser::error(SyntaxErrorKind::MissingOperator, *position),
+        }
     }
 }
 
-pub fn part_1(equations: &[Equation]) -> usize {
-    equations.iter().map(|equation| equation.solve_1()).sum()
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticError {
+    Overflow,
+    DivisionByZero,
+    NegativeExponent,
 }
 
-fn solve_2(tokens: &mut dyn Iterator<Item = Token>) -> usize {
-    #[derive(Debug)]
-    enum Action {
-        AndMultiply(usize),
-        AndAdd(usize),
-        Terminal(usize),
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvaluationError {
+    pub error: ArithmeticError,
+    pub position: usize,
+}
+
+impl EvaluationError {
+    fn message(&self) -> &'static str {
+        match self.error {
+            ArithmeticError::Overflow => "overflow",
+            ArithmeticError::DivisionByZero => "division by zero",
+            ArithmeticError::NegativeExponent => "negative exponent",
+        }
     }
-    let mut actions = Vec::new();
-    while let Some(token) = tokens.next() {
-        let number = match token {
-            Token::Number(num) => num,
-            Token::Open => solve_2(tokens),
-            ilg => panic!("{:?} is illegal here", ilg),
-        };
-        match tokens.next() {
-            Some(Token::Times) => {
-                actions.push(Action::AndMultiply(number));
+
+    pub fn render(&self, line: &str) -> String {
+        caret_diagram(line, self.position, self.message())
+    }
+}
+
+impl core::fmt::Display for EvaluationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {}", self.message(), self.position)
+    }
+}
+
+pub trait Number: Sized {
+    fn from_literal(literal: &str) -> Option<Self>;
+    fn apply(operator: Operator, left: Self, right: Self) -> Result<Self, ArithmeticError>;
+}
+
+impl Number for u64 {
+    fn from_literal(literal: &str) -> Option<Self> {
+        literal.parse().ok()
+    }
+
+    fn apply(operator: Operator, left: Self, right: Self) -> Result<Self, ArithmeticError> {
+        match operator {
+            Operator::Add => left.checked_add(right),
+            Operator::Subtract => left.checked_sub(right),
+            Operator::Multiply => left.checked_mul(right),
+            Operator::Divide => {
+                if right == 0 {
+                    return Err(ArithmeticError::DivisionByZero);
+                }
+                Some(left / right)
             },
-            Some(Token::Plus) => {
-                actions.push(Action::AndAdd(number));
+            Operator::Power => u32::try_from(right).ok().and_then(|right| left.checked_pow(right)),
+        }.ok_or(ArithmeticError::Overflow)
+    }
+}
+
+impl Number for i128 {
+    fn from_literal(literal: &str) -> Option<Self> {
+        literal.parse().ok()
+    }
+
+    fn apply(operator: Operator, left: Self, right: Self) -> Result<Self, ArithmeticError> {
+        match operator {
+            Operator::Add => left.checked_add(right),
+            Operator::Subtract => left.checked_sub(right),
+            Operator::Multiply => left.checked_mul(right),
+            Operator::Divide => {
+                if right == 0 {
+                    return Err(ArithmeticError::DivisionByZero);
+                }
+                left.checked_div(right)
             },
-            None | Some(Token::Close) => {
-                actions.push(Action::Terminal(number));
-                break;
+            Operator::Power => {
+                if right < 0 {
+                    return Err(ArithmeticError::NegativeExponent);
+                }
+                u32::try_from(right).ok().and_then(|right| left.checked_pow(right))
             },
-            ilg => panic!("{:?} is illegal here", ilg),
-        }
+        }.ok_or(ArithmeticError::Overflow)
     }
-    actions.reverse(); // From this point on we want to treat it like a stack
+}
 
-    impl Action {
-        fn modify_number(&mut self, dlg: impl FnOnce(usize) -> usize) {
-            match self {
-                Action::AndMultiply(number) | Action::AndAdd(number) | Action::Terminal(number) => {
-                    *number = dlg(*number);
-                },
-            }
-        }
+impl Number for num::BigInt {
+    fn from_literal(literal: &str) -> Option<Self> {
+        literal.parse().ok()
+    }
+
+    fn apply(operator: Operator, left: Self, right: Self) -> Result<Self, ArithmeticError> {
+        use num::{Zero, Signed, ToPrimitive};
+
+        Ok(match operator {
+            Operator::Add => left + right,
+            Operator::Subtract => left - right,
+            Operator::Multiply => left * right,
+            Operator::Divide => {
+                if right.is_zero() {
+                    return Err(ArithmeticError::DivisionByZero);
+                }
+                left / right
+            },
+            Operator::Power => {
+                if right.is_negative() {
+                    return Err(ArithmeticError::NegativeExponent);
+                }
+                num::pow(left, right.to_usize().ok_or(ArithmeticError::Overflow)?)
+            },
+        })
     }
+}
 
-    fn collapse_additions(actions: &mut Vec<Action>) {
-        while let Some(Action::AndAdd(left)) = actions.last() {
-            let left = *left; // release the borrow so we can pop
-            actions.pop().unwrap();
-            actions.last_mut().unwrap().modify_number(|right| left + right);
+impl Expr {
+    pub fn evaluate<N: Number>(&self) -> Result<N, EvaluationError> {
+        match self {
+            Expr::Number { literal, position } => N::from_literal(literal).ok_or(EvaluationError {
+                error: ArithmeticError::Overflow,
+                position: *position,
+            }),
+            Expr::BinaryOp { operator, position, left, right } => {
+                let (left, right) = (left.evaluate()?, right.evaluate()?);
+                N::apply(*operator, left, right).map_err(|error| EvaluationError {
+                    error,
+                    position: *position,
+                })
+            },
         }
     }
 
-    fn collapse_multiplications(actions: &mut Vec<Action>) {
-        loop {
-            match actions.last().unwrap() {
-                Action::AndAdd(_) => {
-                    collapse_additions(actions);
-                },
-                Action::AndMultiply(left) => {
-                    let left = *left; // release the borrow so we can pop
-                    actions.pop().unwrap();
-                    collapse_additions(actions);
-                    actions.last_mut().unwrap().modify_number(|right| left * right);
-                },
-                Action::Terminal(_) => {
-                    return;
-                },
-            }
+    pub fn display<'a>(&'a self, table: &'a OperatorTable) -> ExprDisplay<'a> {
+        ExprDisplay {
+            expr: self,
+            table,
         }
     }
+}
+
+pub struct ExprDisplay<'a> {
+    expr: &'a Expr,
+    table: &'a OperatorTable,
+}
 
-    collapse_multiplications(&mut actions);
-    if actions.len() == 1 {
-        if let Action::Terminal(result) = actions[0] {
-            return result;
+impl ExprDisplay<'_> {
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter<'_>, operand: &Expr, parent: (u8, Associativity), needs_parens_on_tie: Associativity) -> std::fmt::Result {
+        let needs_parens = if let Expr::BinaryOp { operator, .. } = operand {
+            let (precedence, _) = self.table.get(*operator);
+            precedence < parent.0 || (precedence == parent.0 && parent.1 == needs_parens_on_tie)
+        } else {
+            false
+        };
+        let operand = operand.display(self.table);
+        if needs_parens {
+            write!(f, "({})", operand)
+        } else {
+            write!(f, "{}", operand)
         }
     }
-    panic!("Expected single terminal value, not {:?}", actions);
 }
 
-impl Equation {
-    fn solve_2(&self) -> usize {
-        solve_2(&mut self.0.iter().copied())
+impl core::fmt::Display for ExprDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.expr {
+            Expr::Number { literal, .. } => write!(f, "{}", literal),
+            Expr::BinaryOp { operator, left, right, .. } => {
+                let parent = self.table.get(*operator);
+                self.fmt_operand(f, left, parent, Associativity::Right)?;
+                write!(f, " {} ", operator)?;
+                self.fmt_operand(f, right, parent, Associativity::Left)
+            },
+        }
     }
 }
 
-pub fn part_2(equations: &[Equation]) -> usize {
-    equations.iter().map(|equation| equation.solve_2()).sum()
+fn sum_results(equations: &[Equation], table: &OperatorTable) -> u64 {
+    equations.iter().map(|equation| {
+        let expr = equation.parse(table).unwrap_or_else(|err| panic!("\n{}", err.render(equation.source())));
+        expr.evaluate::<u64>().unwrap_or_else(|err| panic!("\n{}", err.render(equation.source())))
+    }).try_fold(0u64, |sum, result| sum.checked_add(result)).expect("Sum of results overflows")
+}
+
+pub fn part_1(equations: &[Equation]) -> u64 {
+    sum_results(equations, &OperatorTable::left_to_right())
+}
+
+pub fn part_2(equations: &[Equation]) -> u64 {
+    sum_results(equations, &OperatorTable::addition_first())
 }
@@ -1,15 +1,62 @@
 use hashbrown::{HashMap, HashSet};
+use hashbrown::hash_map::Entry;
+use regex::Regex;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[derive(Debug, Clone)]
 pub struct Input {
     rules: HashMap<usize, Rule>,
     messages: Vec<String>,
+    // Filled lazily by `compile_rule`
+    matchers: RefCell<HashMap<usize, Rc<RuleMatcher>>>,
 }
 
 #[derive(Debug, Clone)]
 enum Rule {
-    Character(char),
-    Composition(Vec<Vec<usize>>),
+    Terminal(String),
+    Composition(Vec<Vec<Term>>),
+}
+
+#[derive(Debug, Clone)]
+enum Term {
+    Rule(usize),
+    Terminal(String),
+    Group(Vec<Vec<Term>>),
+}
+
+fn parse_alternation(tokens: &mut std::iter::Peekable<impl Iterator<Item = String>>) -> Vec<Vec<Term>> {
+    let mut options = vec![Vec::new()];
+    while let Some(token) = tokens.next_if(|token| token != ")") {
+        let term = match token.as_str() {
+            "|" => {
+                options.push(Vec::new());
+                continue;
+            },
+            "(" => {
+                let group = parse_alternation(tokens);
+                assert!(tokens.next().as_deref() == Some(")"), "Unclosed group");
+                Term::Group(group)
+            },
+            _ if token.starts_with('"') => Term::Terminal(token.trim_matches('"').to_owned()),
+            rule_num => Term::Rule(rule_num.parse().unwrap()),
+        };
+        options.last_mut().unwrap().push(term);
+    }
+    options
+}
+
+fn parse_rule(rule_text: &str) -> Rule {
+    let pattern = Regex::new(r#"\d+|"[^"]*"|[|()]|\S"#).unwrap();
+    let mut tokens = pattern.find_iter(rule_text).map(|m| m.as_str().to_owned()).peekable();
+    let mut options = parse_alternation(&mut tokens);
+    assert!(tokens.next().is_none(), "Unmatched ')' in {:?}", rule_text);
+    if let [option] = options.as_mut_slice() {
+        if let [Term::Terminal(terminal)] = option.as_mut_slice() {
+            return Rule::Terminal(std::mem::take(terminal));
+        }
+    }
+    Rule::Composition(options)
 }
 
 pub fn generator(input: &str) -> Input {
@@ -18,16 +65,7 @@ pub fn generator(input: &str) -> Input {
         let mut parts_it = line.split(": ");
         let rule_number = parts_it.next().unwrap().parse().unwrap();
         let rule_text = parts_it.next().unwrap();
-        (rule_number, {
-            let mut rule_chars = rule_text.chars();
-            if rule_chars.next() == Some('"') {
-                Rule::Character(rule_chars.next().unwrap())
-            } else {
-                Rule::Composition(rule_text.split(" | ").map(|option_text| {
-                    option_text.split(" ").map(|rule_num| rule_num.parse().unwrap()).collect()
-                }).collect())
-            }
-        })
+        (rule_number, parse_rule(rule_text))
     }).collect();
     // numbered_rules.sort_by_key(|(i, _)| *i);
     // for (i, (j, _)) in numbered_rules.iter().enumerate() {
@@ -37,47 +75,342 @@ pub fn generator(input: &str) -> Input {
 
     let messages = it.map(|line| line.to_owned()).collect();
 
-    Input {rules, messages}
+    Input {rules, messages, matchers: Default::default()}
 }
 
-impl Input {
-    fn message_reminder_after_applying_rule<'a>(&self, message: HashSet<&'a str>, rule_number: usize) -> HashSet<&'a str> {
-        if message.is_empty() {
-            return message;
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Symbol {
+    NonTerminal(usize),
+    Terminal(String),
+}
+
+#[derive(Debug)]
+struct Production {
+    lhs: usize,
+    rhs: Vec<Symbol>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseTree {
+    Terminal(String),
+    Rule {
+        rule_number: usize,
+        children: Vec<ParseTree>,
+    },
+}
+
+// Numbered rules and parenthesized groups both become nonterminals. Groups have no rule number,
+// and their children are spliced into the parent in the parse tree.
+#[derive(Debug)]
+pub struct Grammar {
+    productions: Vec<Production>,
+    productions_by_lhs: Vec<Vec<usize>>,
+    nullable: Vec<bool>,
+    nonterminal_rule_numbers: Vec<Option<usize>>,
+    rule_nonterminals: HashMap<usize, usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Item {
+    production: usize,
+    dot: usize,
+    origin: usize,
+}
+
+#[derive(Default)]
+struct ItemSet {
+    items: Vec<Item>,
+    seen: HashSet<Item>,
+}
+
+impl ItemSet {
+    fn add(&mut self, item: Item) {
+        if self.seen.insert(item) {
+            self.items.push(item);
+        }
+    }
+}
+
+impl Grammar {
+    fn new(rules: &HashMap<usize, Rule>) -> Self {
+        let mut grammar = Grammar {
+            productions: Vec::new(),
+            productions_by_lhs: Vec::new(),
+            nullable: Vec::new(),
+            nonterminal_rule_numbers: Vec::new(),
+            rule_nonterminals: HashMap::new(),
+        };
+        for &rule_number in rules.keys() {
+            grammar.rule_nonterminal(rule_number);
+        }
+        for (&rule_number, rule) in rules.iter() {
+            let lhs = grammar.rule_nonterminals[&rule_number];
+            match rule {
+                Rule::Terminal(terminal) => grammar.add_production(lhs, vec![Symbol::Terminal(terminal.clone())]),
+                Rule::Composition(options) => grammar.add_options(lhs, options),
+            }
         }
-        match &self.rules[&rule_number] {
-            &Rule::Character(c) => message.into_iter().filter_map(|msg| {
-                let mut it = msg.chars();
-                if it.next() == Some(c) {
-                    Some(it.as_str())
-                } else {
-                    None
+        grammar.nullable = vec![false; grammar.nonterminal_rule_numbers.len()];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for production in grammar.productions.iter() {
+                if !grammar.nullable[production.lhs] && production.rhs.iter().all(|symbol| match symbol {
+                    Symbol::NonTerminal(nonterminal) => grammar.nullable[*nonterminal],
+                    Symbol::Terminal(terminal) => terminal.is_empty(),
+                }) {
+                    grammar.nullable[production.lhs] = true;
+                    changed = true;
                 }
-            }).collect(),
-            Rule::Composition(options) => {
-                options.iter().flat_map(|rules_chain| {
-                    let mut rest = message.clone();
-                    for &rule in rules_chain.iter() {
-                        rest = self.message_reminder_after_applying_rule(rest, rule);
-                    }
-                    rest
-                }).collect()
+            }
+        }
+        grammar
+    }
+
+    fn new_nonterminal(&mut self, rule_number: Option<usize>) -> usize {
+        self.nonterminal_rule_numbers.push(rule_number);
+        self.productions_by_lhs.push(Vec::new());
+        self.nonterminal_rule_numbers.len() - 1
+    }
+
+    fn rule_nonterminal(&mut self, rule_number: usize) -> usize {
+        if let Some(&nonterminal) = self.rule_nonterminals.get(&rule_number) {
+            return nonterminal;
+        }
+        let nonterminal = self.new_nonterminal(Some(rule_number));
+        self.rule_nonterminals.insert(rule_number, nonterminal);
+        nonterminal
+    }
+
+    fn add_production(&mut self, lhs: usize, rhs: Vec<Symbol>) {
+        self.productions_by_lhs[lhs].push(self.productions.len());
+        self.productions.push(Production {lhs, rhs});
+    }
+
+    fn add_options(&mut self, lhs: usize, options: &[Vec<Term>]) {
+        for option in options.iter() {
+            let rhs = option.iter().map(|term| match term {
+                Term::Rule(rule_number) => Symbol::NonTerminal(self.rule_nonterminal(*rule_number)),
+                Term::Terminal(terminal) => Symbol::Terminal(terminal.clone()),
+                Term::Group(group_options) => {
+                    let group = self.new_nonterminal(None);
+                    self.add_options(group, group_options);
+                    Symbol::NonTerminal(group)
+                },
+            }).collect();
+            self.add_production(lhs, rhs);
+        }
+    }
+
+    fn chart(&self, start: usize, message: &str) -> Vec<ItemSet> {
+        let mut sets: Vec<ItemSet> = (0..=message.len()).map(|_| ItemSet::default()).collect();
+        for &production in self.productions_by_lhs[start].iter() {
+            sets[0].add(Item {production, dot: 0, origin: 0});
+        }
+        for position in 0..=message.len() {
+            let mut index = 0;
+            while let Some(&item) = sets[position].items.get(index) {
+                index += 1;
+                let production = &self.productions[item.production];
+                let advanced = Item {dot: item.dot + 1, ..item};
+                match production.rhs.get(item.dot) {
+                    None => {
+                        let completed: Vec<Item> = sets[item.origin].items.iter().filter(|waiting| {
+                            self.productions[waiting.production].rhs.get(waiting.dot) == Some(&Symbol::NonTerminal(production.lhs))
+                        }).map(|waiting| Item {dot: waiting.dot + 1, ..*waiting}).collect();
+                        for completed_item in completed {
+                            sets[position].add(completed_item);
+                        }
+                    },
+                    Some(Symbol::NonTerminal(nonterminal)) => {
+                        for &predicted in self.productions_by_lhs[*nonterminal].iter() {
+                            sets[position].add(Item {production: predicted, dot: 0, origin: position});
+                        }
+                        // Completing a nullable nonterminal would happen in this same set, possibly
+                        // before we got here - so we advance over it right away.
+                        if self.nullable[*nonterminal] {
+                            sets[position].add(advanced);
+                        }
+                    },
+                    Some(Symbol::Terminal(terminal)) => {
+                        if message[position..].starts_with(terminal.as_str()) {
+                            sets[position + terminal.len()].add(advanced);
+                        }
+                    },
+                }
+            }
+        }
+        sets
+    }
+
+    fn is_complete(&self, item: &Item, nonterminal: usize, origin: usize) -> bool {
+        let production = &self.productions[item.production];
+        production.lhs == nonterminal && item.dot == production.rhs.len() && item.origin == origin
+    }
+
+    pub fn matches(&self, rule_number: usize, message: &str) -> bool {
+        let start = self.rule_nonterminals[&rule_number];
+        self.chart(start, message)[message.len()].items.iter().any(|item| self.is_complete(item, start, 0))
+    }
+
+    pub fn parse(&self, rule_number: usize, message: &str) -> Option<ParseTree> {
+        let start = self.rule_nonterminals[&rule_number];
+        let chart = self.chart(start, message);
+        let mut builder = TreeBuilder {
+            grammar: self,
+            chart: &chart,
+            message,
+            in_progress: HashSet::new(),
+        };
+        let mut children = builder.build_nonterminal(start, 0, message.len())?;
+        Some(children.pop().unwrap())
+    }
+}
+
+struct TreeBuilder<'a> {
+    grammar: &'a Grammar,
+    chart: &'a [ItemSet],
+    message: &'a str,
+    // Guards against cyclic derivations of an empty span
+    in_progress: HashSet<(usize, usize, usize)>,
+}
+
+impl TreeBuilder<'_> {
+    fn build_nonterminal(&mut self, nonterminal: usize, start: usize, end: usize) -> Option<Vec<ParseTree>> {
+        if !self.in_progress.insert((nonterminal, start, end)) {
+            return None;
+        }
+        let completed: Vec<Item> = self.chart[end].items.iter().filter(|item| self.grammar.is_complete(item, nonterminal, start)).copied().collect();
+        let children = completed.into_iter().find_map(|item| self.build_production(item.production, item.dot, start, end));
+        self.in_progress.remove(&(nonterminal, start, end));
+        let children = children?;
+        Some(match self.grammar.nonterminal_rule_numbers[nonterminal] {
+            Some(rule_number) => vec![ParseTree::Rule {rule_number, children}],
+            None => children,
+        })
+    }
+
+    fn build_production(&mut self, production: usize, dot: usize, origin: usize, end: usize) -> Option<Vec<ParseTree>> {
+        if dot == 0 {
+            return if origin == end { Some(Vec::new()) } else { None };
+        }
+        let prefix_item = Item {production, dot: dot - 1, origin};
+        match &self.grammar.productions[production].rhs[dot - 1] {
+            Symbol::Terminal(terminal) => {
+                let start = end.checked_sub(terminal.len())?;
+                if !self.chart[start].seen.contains(&prefix_item) || &self.message[start..end] != terminal {
+                    return None;
+                }
+                let mut children = self.build_production(production, dot - 1, origin, start)?;
+                children.push(ParseTree::Terminal(terminal.clone()));
+                Some(children)
+            },
+            Symbol::NonTerminal(nonterminal) => {
+                let nonterminal = *nonterminal;
+                let middles: Vec<usize> = self.chart[end].items.iter().filter(|item| {
+                    let item_production = &self.grammar.productions[item.production];
+                    item_production.lhs == nonterminal && item.dot == item_production.rhs.len() && self.chart[item.origin].seen.contains(&prefix_item)
+                }).map(|item| item.origin).collect();
+                middles.into_iter().find_map(|middle| {
+                    let mut children = self.build_production(production, dot - 1, origin, middle)?;
+                    children.extend(self.build_nonterminal(nonterminal, middle, end)?);
+                    Some(children)
+                })
             },
         }
     }
+}
+
+#[derive(Debug)]
+pub enum RuleMatcher {
+    Regex(Box<Regex>),
+    Grammar {
+        grammar: Grammar,
+        rule_number: usize,
+    },
+}
+
+impl RuleMatcher {
+    pub fn matches(&self, message: &str) -> bool {
+        match self {
+            RuleMatcher::Regex(regex) => regex.is_match(message),
+            RuleMatcher::Grammar { grammar, rule_number } => grammar.matches(*rule_number, message),
+        }
+    }
+}
+
+impl Input {
+    fn options_regex_fragment(&self, options: &[Vec<Term>], fragments: &mut HashMap<usize, Option<String>>) -> Option<String> {
+        let options = options.iter().map(|option| {
+            option.iter().map(|term| match term {
+                Term::Rule(rule) => self.regex_fragment(*rule, fragments),
+                Term::Terminal(terminal) => Some(regex::escape(terminal)),
+                Term::Group(group_options) => self.options_regex_fragment(group_options, fragments),
+            }).collect::<Option<String>>()
+        }).collect::<Option<Vec<_>>>()?;
+        Some(if options.len() == 1 {
+            options.into_iter().next().unwrap()
+        } else {
+            format!("(?:{})", options.join("|"))
+        })
+    }
 
-    fn message_matches_rule<'a>(&self, message: &'a str, rule_number: usize) -> bool {
-        self.message_reminder_after_applying_rule([message].iter().copied().collect(), rule_number).into_iter().any(|remaining| remaining == "")
+    // `None` in `fragments` marks a rule we are still in the middle of compiling
+    fn regex_fragment(&self, rule_number: usize, fragments: &mut HashMap<usize, Option<String>>) -> Option<String> {
+        match fragments.entry(rule_number) {
+            Entry::Occupied(entry) => return entry.get().clone(),
+            Entry::Vacant(entry) => {
+                entry.insert(None);
+            },
+        }
+        let fragment = match &self.rules[&rule_number] {
+            Rule::Terminal(terminal) => regex::escape(terminal),
+            Rule::Composition(options) => self.options_regex_fragment(options, fragments)?,
+        };
+        fragments.insert(rule_number, Some(fragment.clone()));
+        Some(fragment)
+    }
+
+    pub fn grammar(&self) -> Grammar {
+        Grammar::new(&self.rules)
     }
+
+    pub fn compile_rule(&self, rule_number: usize) -> Rc<RuleMatcher> {
+        if let Some(matcher) = self.matchers.borrow().get(&rule_number) {
+            return matcher.clone();
+        }
+        let matcher = Rc::new(self.regex_fragment(rule_number, &mut HashMap::new())
+            .and_then(|fragment| Regex::new(&format!("^(?:{})$", fragment)).ok())
+            .map(|regex| RuleMatcher::Regex(Box::new(regex)))
+            .unwrap_or_else(|| RuleMatcher::Grammar {
+                grammar: self.grammar(),
+                rule_number,
+            }));
+        self.matchers.borrow_mut().insert(rule_number, matcher.clone());
+        matcher
+    }
+
+    pub fn parse_trees(&self, rule_number: usize) -> Vec<Option<ParseTree>> {
+        let grammar = self.grammar();
+        self.messages.iter().map(|message| grammar.parse(rule_number, message)).collect()
+    }
+}
+
+fn with_patched_rules(input: &Input) -> Input {
+    let mut rules = input.rules.clone();
+    rules.insert(8, parse_rule("42 | 42 8"));
+    rules.insert(11, parse_rule("42 31 | 42 11 31"));
+    // The cached matchers were compiled from the unpatched rules
+    Input {rules, messages: input.messages.clone(), matchers: Default::default()}
 }
 
 pub fn part_1(input: &Input) -> usize {
-    input.messages.iter().filter(|message| input.message_matches_rule(message, 0)).count()
+    let matcher = input.compile_rule(0);
+    input.messages.iter().filter(|message| matcher.matches(message)).count()
 }
 
 pub fn part_2(input: &Input) -> usize {
-    let mut input = input.clone();
-    input.rules.insert(8, Rule::Composition(vec![vec![42], vec![42, 8]]));
-    input.rules.insert(11, Rule::Composition(vec![vec![42, 31], vec![42, 11, 31]]));
-    input.messages.iter().filter(|message| input.message_matches_rule(message, 0)).count()
+    let input = with_patched_rules(input);
+    let matcher = input.compile_rule(0);
+    input.messages.iter().filter(|message| matcher.matches(message)).count()
 }
@@ -1,10 +1,5 @@
-use core::fmt::Debug;
-use core::ops::Index;
-
 use hashbrown::{HashMap, HashSet};
 
-const SIDE: usize = 10;
-
 const SEA_MONSTER_PATTERN: &[&str] = &[
     "                  # ",
     "#    ##    ##    ###",
@@ -13,6 +8,7 @@ const SEA_MONSTER_PATTERN: &[&str] = &[
 
 #[derive(Debug, Clone)]
 pub struct Tile {
+    side: usize,
     bitmap: Vec<bool>,
 }
 
@@ -25,7 +21,9 @@ pub fn generator(input: &str) -> Input {
     while let Some(header) = it.next() {
         let tile_id = header_pattern.captures(header).unwrap()[1].parse().unwrap();
 
-        let bitmap = it.by_ref().take_while(|&line| line != "").flat_map(|line| {
+        let mut side = 0;
+        let bitmap: Vec<bool> = it.by_ref().take_while(|&line| line != "").flat_map(|line| {
+            side += 1;
             line.chars().map(|c| {
                 match c {
                     '.' => false,
@@ -34,377 +32,638 @@ pub fn generator(input: &str) -> Input {
                 }
             })
         }).collect();
+        assert!(bitmap.len() == side * side, "Tile {} is not square", tile_id);
 
-        result.insert(tile_id, Tile { bitmap });
+        result.insert(tile_id, Tile { side, bitmap });
     }
     result
 }
-impl Index<(usize, usize)> for Tile {
-    type Output = bool;
-
-    fn index(&self, (row, col): (usize, usize)) -> &Self::Output {
-        assert!(col < SIDE);
-        &self.bitmap[row * SIDE + col]
-    }
-}
 
-struct Row<'a> {
-    tile: &'a Tile,
-    row: usize,
+// The elements of the dihedral group D4 - all the ways to rotate and flip a bitmap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Orientation {
+    Identity,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    FlipHorizontal,
+    FlipVertical,
+    Transpose,
+    AntiTranspose,
 }
 
-struct Col<'a> {
-    tile: &'a Tile,
-    col: usize,
-}
-
-impl Tile {
-    fn row(&self, row: usize) -> Row {
-        Row {
-            tile: &self,
-            row,
+impl Orientation {
+    pub const ALL: [Orientation; 8] = [
+        Orientation::Identity,
+        Orientation::Rotate90,
+        Orientation::Rotate180,
+        Orientation::Rotate270,
+        Orientation::FlipHorizontal,
+        Orientation::FlipVertical,
+        Orientation::Transpose,
+        Orientation::AntiTranspose,
+    ];
+
+    pub fn short_name(self) -> &'static str {
+        match self {
+            Orientation::Identity => "I",
+            Orientation::Rotate90 => "R90",
+            Orientation::Rotate180 => "R180",
+            Orientation::Rotate270 => "R270",
+            Orientation::FlipHorizontal => "FH",
+            Orientation::FlipVertical => "FV",
+            Orientation::Transpose => "T",
+            Orientation::AntiTranspose => "AT",
         }
     }
 
-    fn col(&self, col: usize) -> Col {
-        Col {
-            tile: &self,
-            col,
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Transpose | Orientation::AntiTranspose)
+    }
+
+    // Maps a coordinate of the oriented bitmap to a coordinate in the original `height` x `width` bitmap
+    pub fn source(self, (row, col): (usize, usize), (height, width): (usize, usize)) -> (usize, usize) {
+        match self {
+            Orientation::Identity => (row, col),
+            Orientation::Rotate90 => (height - 1 - col, row),
+            Orientation::Rotate180 => (height - 1 - row, width - 1 - col),
+            Orientation::Rotate270 => (col, width - 1 - row),
+            Orientation::FlipHorizontal => (row, width - 1 - col),
+            Orientation::FlipVertical => (height - 1 - row, col),
+            Orientation::Transpose => (col, row),
+            Orientation::AntiTranspose => (height - 1 - col, width - 1 - row),
         }
     }
-}
 
-impl Index<usize> for Row<'_> {
-    type Output = bool;
+    // Applying `self` and then `other` is the same as applying the result
+    pub fn then(self, other: Orientation) -> Orientation {
+        // An element of D4 is determined by where it sends two adjacent corners
+        let dims = (3, 3);
+        let composed = |coord| self.source(other.source(coord, dims), dims);
+        let (corner, next_to_corner) = (composed((0, 0)), composed((0, 1)));
+        *Orientation::ALL.iter().find(|orientation| {
+            orientation.source((0, 0), dims) == corner && orientation.source((0, 1), dims) == next_to_corner
+        }).unwrap()
+    }
 
-    fn index(&self, col: usize) -> &Self::Output {
-        &self.tile[(self.row, col)]
+    pub fn inverse(self) -> Orientation {
+        *Orientation::ALL.iter().find(|&&other| self.then(other) == Orientation::Identity).unwrap()
     }
 }
 
-impl Index<usize> for Col<'_> {
-    type Output = bool;
+pub trait Bitmap {
+    fn height(&self) -> usize;
+    fn width(&self) -> usize;
+    fn get(&self, row: usize, col: usize) -> bool;
 
-    fn index(&self, row: usize) -> &Self::Output {
-        &self.tile[(row, self.col)]
+    fn oriented(&self, orientation: Orientation) -> OrientedView<'_, Self> {
+        OrientedView {
+            inner: self,
+            orientation,
+        }
     }
 }
 
-impl Debug for Row<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use std::fmt::Write;
-        f.write_str("Row[")?;
-        for col in 0..SIDE {
-            f.write_char(if self[col] { '#' } else { '.' })?;
+pub struct OrientedView<'a, B: ?Sized> {
+    inner: &'a B,
+    orientation: Orientation,
+}
+
+impl<'a, B: ?Sized + Bitmap> OrientedView<'a, B> {
+    pub fn reoriented(&self, orientation: Orientation) -> OrientedView<'a, B> {
+        OrientedView {
+            inner: self.inner,
+            orientation: self.orientation.then(orientation),
         }
-        f.write_char(']')?;
-        Ok(())
     }
 }
 
-impl Debug for Col<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        use std::fmt::Write;
-        f.write_str("Col[")?;
-        for row in 0..SIDE {
-            f.write_char(if self[row] { '#' } else { '.' })?;
+impl<B: ?Sized + Bitmap> Bitmap for OrientedView<'_, B> {
+    fn height(&self) -> usize {
+        if self.orientation.swaps_axes() {
+            self.inner.width()
+        } else {
+            self.inner.height()
         }
-        f.write_char(']')?;
-        Ok(())
     }
-}
 
-fn get_code(obj: impl Index<usize, Output = bool>) -> u16 {
-    let mut result = 0;
-    for i in 0..SIDE {
-        result <<= 1;
-        if obj[i] {
-            result |= 1;
+    fn width(&self) -> usize {
+        if self.orientation.swaps_axes() {
+            self.inner.height()
+        } else {
+            self.inner.width()
         }
     }
-    result
-}
 
-fn reverse_code(mut code: u16) -> u16 {
-    let mut result = 0;
-    for _ in 0..SIDE {
-        result <<= 1;
-        result |= code & 1;
-        code >>= 1;
+    fn get(&self, row: usize, col: usize) -> bool {
+        let (row, col) = self.orientation.source((row, col), (self.inner.height(), self.inner.width()));
+        self.inner.get(row, col)
     }
-    result
 }
 
-type CodeMap = HashMap<u16, HashSet<usize>>;
+impl Bitmap for Tile {
+    fn height(&self) -> usize {
+        self.side
+    }
 
-impl Tile {
-    fn border_codes(&self) -> [u16; 4] {
-        [
-            get_code(self.row(0)),
-            get_code(self.col(SIDE - 1)),
-            reverse_code(get_code(self.row(SIDE - 1))),
-            reverse_code(get_code(self.col(0))),
-        ]
-    }
-
-    fn can_be_corner(&self, code_map: &CodeMap) -> bool {
-        self.border_codes().iter().filter(|&&code| {
-            1 < code_map[&reverse_code(code)].len()
-        }).count() == 2
-    }
-
-    fn free_borders(&self, code_map: &CodeMap) -> Vec<usize> {
-        self.border_codes().iter().enumerate().filter_map(|(idx, &code)| {
-            if code_map[&reverse_code(code)].len() == 1 {
-                Some(idx)
-            } else {
-                None
-            }
-        }).collect()
+    fn width(&self) -> usize {
+        self.side
+    }
+
+    fn get(&self, row: usize, col: usize) -> bool {
+        self.bitmap[row * self.side + col]
     }
 }
 
-fn prepare_code_map<'a>(tiles: &Input) -> CodeMap {
-    let mut code_map = CodeMap::new();
-    for (&tile_id, tile) in tiles {
-        for &code in tile.border_codes().iter() {
-            code_map.entry(code).or_insert_with(HashSet::new).insert(tile_id);
-            let reversed = reverse_code(code);
-            assert!(reversed != code);
-            code_map.entry(reversed).or_insert_with(HashSet::new).insert(tile_id);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitImage {
+    height: usize,
+    width: usize,
+    words_per_row: usize,
+    words: Vec<u64>,
+}
+
+impl BitImage {
+    pub fn new(height: usize, width: usize) -> Self {
+        let words_per_row = width.div_ceil(64);
+        Self {
+            height,
+            width,
+            words_per_row,
+            words: vec![0; height * words_per_row],
         }
     }
-    code_map
-}
 
-pub fn part_1(tiles: &Input) -> usize {
-    let code_map = prepare_code_map(tiles);
-    tiles.iter().filter_map(|(tile_id, tile)| {
-        if tile.can_be_corner(&code_map) {
-            Some(tile_id)
+    pub fn from_bitmap(bitmap: &impl Bitmap) -> Self {
+        let mut result = Self::new(bitmap.height(), bitmap.width());
+        for row in 0..bitmap.height() {
+            for col in 0..bitmap.width() {
+                if bitmap.get(row, col) {
+                    result.set(row, col, true);
+                }
+            }
+        }
+        result
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, value: bool) {
+        let word = &mut self.words[row * self.words_per_row + col / 64];
+        if value {
+            *word |= 1 << (col % 64);
         } else {
-            None
+            *word &= !(1 << (col % 64));
         }
-    }).product()
-}
+    }
 
-fn transpose_bitmap(bitmap: &mut [bool], side: usize) {
-    for row in 0..side {
-        for col in 0..row {
-            bitmap.swap(row * side + col, col * side + row);
+    // Up to 64 bits of a row starting at `col`, with `col` in the lowest bit
+    pub fn window(&self, row: usize, col: usize, len: usize) -> u64 {
+        let row_words = &self.words[row * self.words_per_row..(row + 1) * self.words_per_row];
+        let (word_index, shift) = (col / 64, col % 64);
+        let mut result = row_words.get(word_index).map_or(0, |word| word >> shift);
+        if 0 < shift {
+            result |= row_words.get(word_index + 1).map_or(0, |word| word << (64 - shift));
+        }
+        if len < 64 {
+            result &= (1 << len) - 1;
         }
+        result
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.words.iter().map(|word| word.count_ones() as usize).sum()
     }
-}
 
-fn hflip_bitmap(bitmap: &mut [bool], side: usize) {
-    for row in 0..side {
-        for col in 0..(side / 2) {
-            bitmap.swap(row * side + col, row * side + side - col - 1)
+    pub fn to_pbm(&self) -> String {
+        let mut result = format!("P1\n{} {}\n", self.width, self.height);
+        for row in 0..self.height {
+            let row_pixels: Vec<&str> = (0..self.width).map(|col| if self.get(row, col) { "1" } else { "0" }).collect();
+            result.push_str(&row_pixels.join(" "));
+            result.push('\n');
         }
+        result
     }
 }
 
-fn vflip_bitmap(bitmap: &mut [bool], side: usize) {
-    for row in 0..(side / 2) {
-        for col in 0..side {
-            bitmap.swap(row * side + col, (side - row - 1) * side + col)
-        }
+impl Bitmap for BitImage {
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn get(&self, row: usize, col: usize) -> bool {
+        self.words[row * self.words_per_row + col / 64] & (1 << (col % 64)) != 0
     }
 }
 
-#[derive(Debug)]
-enum BorderMatch {
-    Straight(usize),
-    Reversed(usize),
+#[derive(Debug, Clone, Copy)]
+enum Edge {
+    Top,
+    Right,
+    Bottom,
+    Left,
 }
 
 impl Tile {
-    fn transposed(mut self) -> Tile {
-        transpose_bitmap(self.bitmap.as_mut_slice(), SIDE);
-        self
+    // Top and bottom edges are read left to right, left and right edges are read top to bottom
+    fn edge(&self, orientation: Orientation, edge: Edge) -> Vec<bool> {
+        let view = self.oriented(orientation);
+        let last = self.side - 1;
+        (0..self.side).map(|i| {
+            let (row, col) = match edge {
+                Edge::Top => (0, i),
+                Edge::Right => (i, last),
+                Edge::Bottom => (last, i),
+                Edge::Left => (i, 0),
+            };
+            view.get(row, col)
+        }).collect()
     }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Placement {
+    pub tile_id: usize,
+    pub orientation: Orientation,
+}
 
-    fn hflipped(mut self) -> Tile {
-        hflip_bitmap(self.bitmap.as_mut_slice(), SIDE);
-        self
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PlacedTile {
+    pub tile_id: usize,
+    pub row: usize,
+    pub col: usize,
+    pub orientation: Orientation,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Borders {
+    Keep,
+    Strip,
+}
+
+#[derive(Debug)]
+pub struct Layout {
+    rows: usize,
+    cols: usize,
+    tile_side: usize,
+    placed: Vec<PlacedTile>,
+}
+
+impl Layout {
+    pub fn placed_tiles(&self) -> &[PlacedTile] {
+        &self.placed
+    }
+
+    pub fn at(&self, row: usize, col: usize) -> PlacedTile {
+        self.placed[row * self.cols + col]
+    }
+
+    // Clockwise from the top left. A single row or column only has its two ends as corners.
+    pub fn corners(&self) -> Vec<PlacedTile> {
+        let mut corners = vec![
+            self.at(0, 0),
+            self.at(0, self.cols - 1),
+            self.at(self.rows - 1, self.cols - 1),
+            self.at(self.rows - 1, 0),
+        ];
+        corners.dedup();
+        if 1 < corners.len() && corners.first() == corners.last() {
+            corners.pop();
+        }
+        corners
     }
 
-    fn vflipped(mut self) -> Tile {
-        vflip_bitmap(self.bitmap.as_mut_slice(), SIDE);
-        self
+    fn render_tiles(&self, tiles: &Input, borders: Borders, mut dlg: impl FnMut(usize, usize, bool, bool)) {
+        let (skip, side) = match borders {
+            Borders::Keep => (0, self.tile_side),
+            Borders::Strip => (1, self.tile_side - 2),
+        };
+        for placed in self.placed.iter() {
+            let view = tiles[&placed.tile_id].oriented(placed.orientation);
+            for subrow in 0..side {
+                for subcol in 0..side {
+                    let (row, col) = (subrow + skip, subcol + skip);
+                    let on_border = row == 0 || col == 0 || row == self.tile_side - 1 || col == self.tile_side - 1;
+                    dlg(placed.row * side + subrow, placed.col * side + subcol, view.get(row, col), on_border);
+                }
+            }
+        }
     }
 
-    fn matching_border(&self, code_to_match: u16) -> BorderMatch {
-        let reversed_to_match = reverse_code(code_to_match);
-        self.border_codes().iter().enumerate().filter_map(|(i, &code)| {
-            if code == code_to_match {
-                Some(BorderMatch::Reversed(i))
-            } else if code == reversed_to_match {
-                Some(BorderMatch::Straight(i))
-            } else {
-                None
+    pub fn render(&self, tiles: &Input, borders: Borders) -> BitImage {
+        let side = match borders {
+            Borders::Keep => self.tile_side,
+            Borders::Strip => self.tile_side - 2,
+        };
+        let mut result = BitImage::new(self.rows * side, self.cols * side);
+        self.render_tiles(tiles, borders, |row, col, value, _| {
+            if value {
+                result.set(row, col, true);
             }
-        }).next().unwrap()
+        });
+        result
+    }
+
+    // Squishes the tiles into one image, without their borders
+    pub fn image(&self, tiles: &Input) -> BitImage {
+        self.render(tiles, Borders::Strip)
+    }
+
+    // Keeps the borders, but draws them in gray so the tiles stand out
+    pub fn to_pgm(&self, tiles: &Input) -> String {
+        let (height, width) = (self.rows * self.tile_side, self.cols * self.tile_side);
+        let mut pixels = vec![0u8; height * width];
+        self.render_tiles(tiles, Borders::Keep, |row, col, value, on_border| {
+            pixels[row * width + col] = match (value, on_border) {
+                (true, false) => 0,
+                (true, true) => 96,
+                (false, true) => 192,
+                (false, false) => 255,
+            };
+        });
+        let mut result = format!("P2\n{} {}\n255\n", width, height);
+        for row_pixels in pixels.chunks(width) {
+            let row_pixels: Vec<String> = row_pixels.iter().map(|pixel| pixel.to_string()).collect();
+            result.push_str(&row_pixels.join(" "));
+            result.push('\n');
+        }
+        result
+    }
+
+    pub fn describe(&self) -> String {
+        let cells: Vec<String> = self.placed.iter().map(|placed| format!("{}:{}", placed.tile_id, placed.orientation.short_name())).collect();
+        let cell_width = cells.iter().map(|cell| cell.len()).max().unwrap_or(0);
+        let mut result = String::new();
+        for row_cells in cells.chunks(self.cols) {
+            let row_cells: Vec<String> = row_cells.iter().map(|cell| format!("{:<width$}", cell, width = cell_width)).collect();
+            result.push_str(row_cells.join("  ").trim_end());
+            result.push('\n');
+        }
+        result
     }
 }
 
-fn organize_tiles(tiles: &Input) -> Vec<Vec<Tile>> {
-    let code_map = prepare_code_map(tiles);
-    let big_side = (tiles.len() as f64).sqrt() as usize;
-    let mut result: Vec<Vec<Tile>> = Vec::new();
-    let mut used_tiles = HashSet::new();
+type EdgeMap = HashMap<(usize, Orientation), [Vec<bool>; 4]>;
 
-    let get_matching_tile = |code, prev_tile_id| {
-        *code_map[&code].iter().filter(|&&tile_id| tile_id != prev_tile_id).next().unwrap()
-    };
+struct Assembler {
+    rows: usize,
+    cols: usize,
+    edges: EdgeMap,
+    by_left_edge: HashMap<Vec<bool>, Vec<Placement>>,
+    by_top_edge: HashMap<Vec<bool>, Vec<Placement>>,
+    starting_candidates: Vec<Placement>,
+}
 
-    let mut prev_row_start = None;
-    for _ in 0..big_side {
-        let mut current_row = Vec::new();
-        let mut prev_tile_id = if let Some(prev_row_start) = prev_row_start {
-            let code_to_match = reverse_code(get_code(result.last().unwrap()[0].row(SIDE - 1)));
-            let tile_id = get_matching_tile(code_to_match, prev_row_start);
-            let tile = tiles[&tile_id].clone();
-            current_row.push(match tile.matching_border(code_to_match) {
-                BorderMatch::Straight(0) => tile,
-                BorderMatch::Straight(1) => tile.transposed().vflipped(),
-                BorderMatch::Straight(2) => tile.vflipped().hflipped(),
-                BorderMatch::Straight(3) => tile.transposed().hflipped(),
-                BorderMatch::Reversed(0) => tile.hflipped(),
-                BorderMatch::Reversed(1) => tile.transposed().hflipped().vflipped(),
-                BorderMatch::Reversed(2) => tile.vflipped(),
-                BorderMatch::Reversed(3) => tile.transposed(),
-                ilg => panic!("Illegal {:?}", ilg),
-            });
-            tile_id
+impl Assembler {
+    fn edge(&self, placement: &Placement, edge: Edge) -> &[bool] {
+        &self.edges[&(placement.tile_id, placement.orientation)][edge as usize]
+    }
+
+    fn search(&self, grid: &mut Vec<Placement>, used: &mut HashSet<usize>) -> bool {
+        let position = grid.len();
+        if position == self.rows * self.cols {
+            return true;
+        }
+        let (row, col) = (position / self.cols, position % self.cols);
+        let candidates = if 0 < col {
+            self.by_left_edge.get(self.edge(&grid[position - 1], Edge::Right))
+        } else if 0 < row {
+            self.by_top_edge.get(self.edge(&grid[position - self.cols], Edge::Bottom))
         } else {
-            let (&corner_id, corner_tile) = tiles.iter().find(|(_, tile)| tile.can_be_corner(&code_map)).unwrap();
-            used_tiles.insert(corner_id);
-
-            let tile = match corner_tile.free_borders(&code_map).as_slice() {
-                &[0, 1] => corner_tile.clone().transposed().vflipped(),
-                &[1, 2] => corner_tile.clone().hflipped().vflipped(),
-                &[2, 3] => corner_tile.clone().transposed().hflipped(),
-                &[0, 3] => corner_tile.clone(),
-                _ => panic!("")
-            };
-            current_row.push(tile);
-            corner_id
+            Some(&self.starting_candidates)
         };
-        prev_row_start = Some(prev_tile_id);
-
-        for _ in 1..big_side {
-            let code_to_match = get_code(current_row.last().unwrap().col(SIDE - 1));
-            let tile_id = get_matching_tile(code_to_match, prev_tile_id);
-            prev_tile_id = tile_id;
-            let tile = tiles[&tile_id].clone();
-            current_row.push(match tile.matching_border(code_to_match) {
-                BorderMatch::Straight(0) => tile.transposed().vflipped(),
-                BorderMatch::Straight(1) => tile.hflipped().vflipped(),
-                BorderMatch::Straight(2) => tile.transposed().hflipped(),
-                BorderMatch::Straight(3) => tile,
-                BorderMatch::Reversed(0) => tile.transposed(),
-                BorderMatch::Reversed(1) => tile.hflipped(),
-                BorderMatch::Reversed(2) => tile.transposed().hflipped().vflipped(),
-                BorderMatch::Reversed(3) => tile.vflipped(),
-                ilg => panic!("Illegal {:?}", ilg),
-            });
+        for candidate in candidates.into_iter().flatten() {
+            if used.contains(&candidate.tile_id) {
+                continue;
+            }
+            if 0 < row && self.edge(&grid[position - self.cols], Edge::Bottom) != self.edge(candidate, Edge::Top) {
+                continue;
+            }
+            used.insert(candidate.tile_id);
+            grid.push(*candidate);
+            if self.search(grid, used) {
+                return true;
+            }
+            grid.pop();
+            used.remove(&candidate.tile_id);
         }
-        result.push(current_row);
+        false
     }
+}
 
-    result
+fn all_edges(tiles: &Input) -> EdgeMap {
+    let side = tiles.values().next().map_or(0, |tile| tile.side);
+    assert!(tiles.values().all(|tile| tile.side == side), "All tiles must have the same side");
+    tiles.iter().flat_map(|(&tile_id, tile)| {
+        Orientation::ALL.iter().map(move |&orientation| {
+            ((tile_id, orientation), [
+                tile.edge(orientation, Edge::Top),
+                tile.edge(orientation, Edge::Right),
+                tile.edge(orientation, Edge::Bottom),
+                tile.edge(orientation, Edge::Left),
+            ])
+        })
+    }).collect()
 }
 
-fn squish_organized_tiles(organized: &Vec<Vec<Tile>>) -> Vec<bool> {
-    let mut result = Vec::new();
-    for tiles_row in organized.iter() {
-        for subrow in 1..(SIDE - 1) {
-            for tile in tiles_row.iter() {
-                for subcol in 1..(SIDE - 1) {
-                    result.push(tile[(subrow, subcol)]);
-                }
-            }
+// Number of edges of each tile that no other tile can be matched against
+fn unmatched_edges_count(tiles: &Input, edges: &EdgeMap) -> HashMap<usize, usize> {
+    let mut tiles_by_edge: HashMap<&[bool], HashSet<usize>> = HashMap::new();
+    for (&(tile_id, _), tile_edges) in edges.iter() {
+        for edge in tile_edges.iter() {
+            tiles_by_edge.entry(edge.as_slice()).or_default().insert(tile_id);
         }
     }
-    result
+    tiles.keys().map(|&tile_id| {
+        let count = edges[&(tile_id, Orientation::Identity)].iter().filter(|edge| tiles_by_edge[edge.as_slice()].len() == 1).count();
+        (tile_id, count)
+    }).collect()
+}
+
+// Layouts that fit the tiles' unmatched edges come first, then the rest from the squarest
+fn layout_candidates(tiles: &Input) -> Vec<(usize, usize)> {
+    let edges = all_edges(tiles);
+    // How many tiles have each number of unmatched edges
+    let mut histogram = [0; 5];
+    for count in unmatched_edges_count(tiles, &edges).values() {
+        histogram[*count] += 1;
+    }
+    let expected_histogram = |rows: usize, cols: usize| {
+        match (rows, cols) {
+            (1, 1) => [0, 0, 0, 0, 1],
+            // The two ends of a strip have 3 unmatched edges, and the tiles between them have 2
+            (1, length) | (length, 1) => [0, 0, length - 2, 2, 0],
+            _ => [(rows - 2) * (cols - 2), 2 * (rows - 2) + 2 * (cols - 2), 4, 0, 0],
+        }
+    };
+    let count = tiles.len();
+    let mut candidates: Vec<(usize, usize)> = (1..=count).filter(|rows| num::Integer::is_multiple_of(&count, rows)).map(|rows| (rows, count / rows)).filter(|&(rows, cols)| rows <= cols).collect();
+    candidates.sort_by_key(|&(rows, cols)| (expected_histogram(rows, cols) != histogram, std::cmp::Reverse(rows)));
+    candidates
+}
+
+pub fn infer_layout(tiles: &Input) -> Option<(usize, usize)> {
+    layout_candidates(tiles).first().copied()
+}
+
+pub fn assemble(tiles: &Input, rows: usize, cols: usize) -> Option<Layout> {
+    assert!(rows * cols == tiles.len(), "{} tiles cannot fill a {}x{} layout", tiles.len(), rows, cols);
+    let edges = all_edges(tiles);
+    let unmatched = unmatched_edges_count(tiles, &edges);
+
+    let mut placements: Vec<Placement> = edges.keys().map(|&(tile_id, orientation)| Placement {tile_id, orientation}).collect();
+    // Corners first, since they are the most likely to start a valid assembly
+    placements.sort_by_key(|placement| (std::cmp::Reverse(unmatched[&placement.tile_id]), placement.tile_id, placement.orientation as usize));
+
+    let mut by_left_edge: HashMap<Vec<bool>, Vec<Placement>> = HashMap::new();
+    let mut by_top_edge: HashMap<Vec<bool>, Vec<Placement>> = HashMap::new();
+    for placement in placements.iter() {
+        let placement_edges = &edges[&(placement.tile_id, placement.orientation)];
+        by_left_edge.entry(placement_edges[Edge::Left as usize].clone()).or_default().push(*placement);
+        by_top_edge.entry(placement_edges[Edge::Top as usize].clone()).or_default().push(*placement);
+    }
+    let assembler = Assembler {
+        rows,
+        cols,
+        edges,
+        by_left_edge,
+        by_top_edge,
+        starting_candidates: placements,
+    };
+    let mut grid = Vec::with_capacity(rows * cols);
+    if assembler.search(&mut grid, &mut HashSet::new()) {
+        let placed = grid.into_iter().enumerate().map(|(position, placement)| PlacedTile {
+            tile_id: placement.tile_id,
+            row: position / cols,
+            col: position % cols,
+            orientation: placement.orientation,
+        }).collect();
+        let tile_side = tiles.values().next().unwrap().side;
+        Some(Layout {rows, cols, tile_side, placed})
+    } else {
+        None
+    }
+}
+
+// Accidental edge matches can make the inferred layout wrong, so fall back to the other candidates
+fn assemble_inferred(tiles: &Input) -> Layout {
+    layout_candidates(tiles).into_iter().find_map(|(rows, cols)| assemble(tiles, rows, cols)).expect("Tiles cannot be assembled")
+}
+
+pub fn part_1(tiles: &Input) -> usize {
+    assemble_inferred(tiles).corners().iter().map(|placement| placement.tile_id).product()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PatternMatch {
+    pub row: usize,
+    pub col: usize,
+    pub orientation: Orientation,
 }
 
 #[derive(Debug)]
-struct SeaMonsterPattern(Vec<(usize, usize)>);
+pub struct Pattern {
+    // Only the distinct orientations - a symmetric pattern should not match the same cells twice
+    oriented: Vec<(Orientation, BitImage)>,
+}
 
-impl SeaMonsterPattern {
-    fn new(pattern: &[&str]) -> Self {
-        Self(pattern.iter().enumerate().flat_map(|(row, row_data)| {
-            row_data.chars().enumerate().filter_map(move |(col, c)| {
+impl Pattern {
+    pub fn new(pattern: &[&str]) -> Self {
+        let mut image = BitImage::new(pattern.len(), pattern.iter().map(|row_data| row_data.len()).max().unwrap_or(0));
+        for (row, row_data) in pattern.iter().enumerate() {
+            for (col, c) in row_data.chars().enumerate() {
                 if c == '#' {
-                    Some((row, col))
-                } else {
-                    None
+                    image.set(row, col, true);
                 }
-            })
-        }).collect())
-    }
-
-    fn is_at(&self, side: usize, map: &Vec<bool>, row: usize, col: usize) -> bool {
-        for (r, c) in self.0.iter() {
-            if !map[(row + r) * side + col + c] {
-                return false;
             }
         }
-        true
+        let mut oriented: Vec<(Orientation, BitImage)> = Vec::new();
+        for &orientation in Orientation::ALL.iter() {
+            let oriented_image = BitImage::from_bitmap(&image.oriented(orientation));
+            if oriented.iter().all(|(_, existing)| *existing != oriented_image) {
+                oriented.push((orientation, oriented_image));
+            }
+        }
+        Self {oriented}
     }
 
-    fn remove_at(&self, side: usize, map: &mut Vec<bool>, row: usize, col: usize) {
-        for (r, c) in self.0.iter() {
-            map[(row + r) * side + col + c] = false;
-        }
+    fn oriented(&self, orientation: Orientation) -> &BitImage {
+        &self.oriented.iter().find(|(existing, _)| *existing == orientation).unwrap().1
     }
 
-    fn find_all(&self, map: &Vec<bool>) -> Vec<(usize, usize)> {
-        let side = (map.len() as f64).sqrt() as usize;
+    fn is_at(pattern: &BitImage, image: &BitImage, row: usize, col: usize) -> bool {
+        (0..pattern.height).all(|r| {
+            (0..pattern.width).step_by(64).all(|start| {
+                let len = (pattern.width - start).min(64);
+                let pattern_bits = pattern.window(r, start, len);
+                image.window(row + r, col + start, len) & pattern_bits == pattern_bits
+            })
+        })
+    }
+
+    // A single sweep over the image, trying every orientation at each position
+    pub fn find_all(&self, image: &BitImage) -> Vec<PatternMatch> {
         let mut result = Vec::new();
-        for row in 0..(side - SEA_MONSTER_PATTERN.len()) {
-            for col in 0..(side - SEA_MONSTER_PATTERN[0].len()) {
-                if self.is_at(side, map, row, col) {
-                    result.push((row, col));
+        for row in 0..image.height {
+            for col in 0..image.width {
+                for (orientation, pattern) in self.oriented.iter() {
+                    if image.height < row + pattern.height || image.width < col + pattern.width {
+                        continue;
+                    }
+                    if Self::is_at(pattern, image, row, col) {
+                        result.push(PatternMatch {
+                            row,
+                            col,
+                            orientation: *orientation,
+                        });
+                    }
                 }
             }
         }
         result
     }
-}
-
 
-pub fn part_2(tiles: &Input) -> usize {
-    let organized = organize_tiles(tiles);
-
-    let squished = squish_organized_tiles(&organized);
-    let side = (squished.len() as f64).sqrt() as usize;
-
-    let sea_monster_pattern = SeaMonsterPattern::new(SEA_MONSTER_PATTERN);
+    pub fn cells<'a>(&'a self, pattern_match: &'a PatternMatch) -> impl 'a + Iterator<Item = (usize, usize)> {
+        let pattern = self.oriented(pattern_match.orientation);
+        (0..pattern.height).flat_map(move |r| {
+            (0..pattern.width).filter(move |&c| pattern.get(r, c)).map(move |c| (pattern_match.row + r, pattern_match.col + c))
+        })
+    }
 
-    let (mut map, monsters) = (0..0b1000).find_map(|choice| {
-        let mut map = squished.clone();
-        if 0 != (choice & 0b1) {
-            transpose_bitmap(&mut map, side);
-        }
-        if 0 != (choice & 0b10) {
-            hflip_bitmap(&mut map, side);
-        }
-        if 0 != (choice & 0b100) {
-            vflip_bitmap(&mut map, side);
-        }
-        let monsters = sea_monster_pattern.find_all(&map);
-        if monsters.is_empty() {
-            None
-        } else {
-            Some((map, monsters))
+    pub fn matched_cells(&self, image: &BitImage, matches: &[PatternMatch]) -> BitImage {
+        let mut result = BitImage::new(image.height, image.width);
+        for pattern_match in matches.iter() {
+            for (row, col) in self.cells(pattern_match) {
+                result.set(row, col, true);
+            }
         }
-    }).unwrap();
+        result
+    }
 
-    for (r, c) in monsters {
-        sea_monster_pattern.remove_at(side, &mut map, r, c);
+    pub fn render(&self, image: &BitImage, matches: &[PatternMatch]) -> String {
+        let matched = self.matched_cells(image, matches);
+        let mut result = String::new();
+        for row in 0..image.height {
+            for col in 0..image.width {
+                result.push(if matched.get(row, col) {
+                    'O'
+                } else if image.get(row, col) {
+                    '#'
+                } else {
+                    '.'
+                });
+            }
+            result.push('\n');
+        }
+        result
     }
-    map.iter().filter(|&&value| value).count()
+}
+
+pub fn part_2(tiles: &Input) -> usize {
+    let image = assemble_inferred(tiles).image(tiles);
+    let sea_monster = Pattern::new(SEA_MONSTER_PATTERN);
+    let monsters = sea_monster.find_all(&image);
+    image.count_ones() - sea_monster.matched_cells(&image, &monsters).count_ones()
 }
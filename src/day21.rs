@@ -8,63 +8,216 @@ pub struct FoodItem {
 }
 
 pub fn generator(input: &str) -> Vec<FoodItem> {
-    let pattern = regex::Regex::new(r"^(.*) \(contains (.*)\)$").unwrap();
+    let pattern = regex::Regex::new(r"^(.*?)(?: \(contains (.*)\))?$").unwrap();
     input.lines().map(|line| {
         let m = pattern.captures(line).unwrap();
         FoodItem {
-            ingredients: m[1].split(" ").map(str::to_owned).collect(),
-            allergens: m[2].split(", ").map(str::to_owned).collect(),
+            ingredients: m[1].split(' ').map(str::to_owned).collect(),
+            allergens: m.get(2).map_or_else(Vec::new, |allergens| allergens.as_str().split(", ").map(str::to_owned).collect()),
         }
     }).collect()
 }
 
-fn calc_initial_allergens_possibilities(food_items: &[FoodItem]) -> HashMap::<&str, HashSet<&str>> {
-    let mut result = HashMap::<&str, HashSet<&str>>::new();
-    for food_item in food_items.iter() {
-        let ingredients = food_item.ingredients.iter().map(|ing| ing.as_str()).collect::<HashSet<_>>();
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+#[derive(Debug, Default)]
+pub struct Interner {
+    names: Vec<String>,
+    symbols: HashMap<String, Symbol>,
+}
+
+impl Interner {
+    pub fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(&symbol) = self.symbols.get(name) {
+            return symbol;
+        }
+        let symbol = Symbol(self.names.len() as u32);
+        self.names.push(name.to_owned());
+        self.symbols.insert(name.to_owned(), symbol);
+        symbol
+    }
+
+    pub fn name(&self, symbol: Symbol) -> &str {
+        &self.names[symbol.0 as usize]
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct AllergenKnowledgeBase {
+    ingredients: Interner,
+    allergens: Interner,
+    ingredient_occurrences: HashMap<Symbol, usize>,
+    candidates: HashMap<Symbol, HashSet<Symbol>>,
+    determined: HashMap<Symbol, Symbol>,
+}
+
+impl AllergenKnowledgeBase {
+    pub fn from_food_items(food_items: &[FoodItem]) -> Self {
+        let mut result = Self::default();
+        for food_item in food_items.iter() {
+            result.add_food_item(food_item);
+        }
+        result
+    }
+
+    pub fn ingredients(&self) -> &Interner {
+        &self.ingredients
+    }
+
+    pub fn allergens(&self) -> &Interner {
+        &self.allergens
+    }
+
+    pub fn determined(&self) -> &HashMap<Symbol, Symbol> {
+        &self.determined
+    }
+
+    // Returns the allergens that became fully determined by this item, with their ingredients
+    pub fn add_food_item(&mut self, food_item: &FoodItem) -> Vec<(Symbol, Symbol)> {
+        let ingredients: HashSet<Symbol> = food_item.ingredients.iter().map(|ingredient| self.ingredients.intern(ingredient)).collect();
+        for ingredient in food_item.ingredients.iter() {
+            *self.ingredient_occurrences.entry(self.ingredients.intern(ingredient)).or_default() += 1;
+        }
         for allergen in food_item.allergens.iter() {
-            match result.entry(allergen) {
+            let allergen = self.allergens.intern(allergen);
+            match self.candidates.entry(allergen) {
                 Entry::Occupied(mut entry) => {
-                    let new_value = entry.get().intersection(&ingredients);
-                    *entry.get_mut() = new_value.map(|s| *s).collect();
-                }
+                    entry.get_mut().retain(|ingredient| ingredients.contains(ingredient));
+                },
                 Entry::Vacant(entry) => {
-                    entry.insert(ingredients.clone());
+                    let taken: HashSet<Symbol> = self.determined.values().copied().collect();
+                    entry.insert(ingredients.difference(&taken).copied().collect());
+                },
+            }
+        }
+        self.propagate()
+    }
+
+    fn propagate(&mut self) -> Vec<(Symbol, Symbol)> {
+        let mut newly_determined = Vec::new();
+        while let Some((allergen, ingredient)) = self.candidates.iter().find_map(|(&allergen, ingredients)| {
+            if ingredients.len() == 1 && !self.determined.contains_key(&allergen) {
+                Some((allergen, *ingredients.iter().next().unwrap()))
+            } else {
+                None
+            }
+        }) {
+            self.determined.insert(allergen, ingredient);
+            newly_determined.push((allergen, ingredient));
+            for (&other_allergen, ingredients) in self.candidates.iter_mut() {
+                if other_allergen != allergen {
+                    ingredients.remove(&ingredient);
+                }
+            }
+        }
+        newly_determined
+    }
+
+    pub fn safe_ingredients(&self) -> Vec<(&str, usize)> {
+        let ingredients_with_allergens: HashSet<Symbol> = self.candidates.values().flatten().copied().collect();
+        let mut result: Vec<_> = self.ingredient_occurrences.iter().filter(|(ingredient, _)| {
+            !ingredients_with_allergens.contains(*ingredient)
+        }).map(|(&ingredient, &count)| (self.ingredients.name(ingredient), count)).collect();
+        result.sort();
+        result
+    }
+}
+
+#[derive(Debug)]
+pub enum AllergenSolution<'a> {
+    Solved(Vec<(&'a str, &'a str)>),
+    Ambiguous(Vec<(&'a str, Vec<&'a str>)>),
+    Contradiction(Vec<&'a str>),
+}
+
+type Candidates = HashMap<Symbol, HashSet<Symbol>>;
+
+// Returns the allergen each ingredient is matched with, or the allergens that could not be matched
+fn find_matching(candidates: &Candidates, allergens: &[Symbol]) -> Result<HashMap<Symbol, Symbol>, Vec<Symbol>> {
+    fn try_augment(allergen: Symbol, candidates: &Candidates, visited: &mut HashSet<Symbol>, owners: &mut HashMap<Symbol, Symbol>) -> bool {
+        for &ingredient in candidates[&allergen].iter() {
+            if !visited.insert(ingredient) {
+                continue;
+            }
+            let can_take = match owners.get(&ingredient) {
+                None => true,
+                Some(&owner) => try_augment(owner, candidates, visited, owners),
+            };
+            if can_take {
+                owners.insert(ingredient, allergen);
+                return true;
+            }
+        }
+        false
+    }
+
+    let mut owners = HashMap::new();
+    let unmatched: Vec<Symbol> = allergens.iter().copied().filter(|&allergen| {
+        !try_augment(allergen, candidates, &mut HashSet::new(), &mut owners)
+    }).collect();
+    if unmatched.is_empty() {
+        Ok(owners)
+    } else {
+        Err(unmatched)
+    }
+}
+
+impl AllergenKnowledgeBase {
+    pub fn solve(&self) -> AllergenSolution<'_> {
+        let allergen_name = |allergen| self.allergens.name(allergen);
+        let ingredient_name = |ingredient| self.ingredients.name(ingredient);
+        let mut allergens: Vec<Symbol> = self.candidates.keys().copied().collect();
+        allergens.sort_by_key(|&allergen| allergen_name(allergen));
+
+        let owners = match find_matching(&self.candidates, &allergens) {
+            Ok(owners) => owners,
+            Err(unmatched) => return AllergenSolution::Contradiction(unmatched.into_iter().map(allergen_name).collect()),
+        };
+        let matched_with: HashMap<Symbol, Symbol> = owners.iter().map(|(&ingredient, &allergen)| (allergen, ingredient)).collect();
+
+        let mut ambiguous = Vec::new();
+        for &allergen in allergens.iter() {
+            let mut candidates: Vec<&str> = self.candidates[&allergen].iter().copied().filter(|&ingredient| {
+                if ingredient == matched_with[&allergen] {
+                    return true;
                 }
+                let mut fixed = self.candidates.clone();
+                for (&other_allergen, ingredients) in fixed.iter_mut() {
+                    if other_allergen == allergen {
+                        *ingredients = [ingredient].iter().copied().collect();
+                    } else {
+                        ingredients.remove(&ingredient);
+                    }
+                }
+                find_matching(&fixed, &allergens).is_ok()
+            }).map(ingredient_name).collect();
+            if 1 < candidates.len() {
+                candidates.sort();
+                ambiguous.push((allergen_name(allergen), candidates));
             }
         }
+
+        if ambiguous.is_empty() {
+            AllergenSolution::Solved(allergens.into_iter().map(|allergen| (allergen_name(allergen), ingredient_name(matched_with[&allergen]))).collect())
+        } else {
+            AllergenSolution::Ambiguous(ambiguous)
+        }
     }
-    result
 }
 
 pub fn part_1(food_items: &[FoodItem]) -> usize {
-    let allergens_possibilities = calc_initial_allergens_possibilities(food_items);
-    let ingredients_with_allergens: HashSet<_> = allergens_possibilities.values().flatten().copied().collect();
-    let all_ingredients: HashSet<_> = food_items.iter()
-        .flat_map(|fi| fi.ingredients.iter().map(String::as_str))
-        .collect();
-    let ingredients_with_no_allergens: HashSet<_> = all_ingredients.difference(&ingredients_with_allergens).copied().collect();
-    food_items.iter().flat_map(|fi| fi.ingredients.iter()).filter(|ing| ingredients_with_no_allergens.contains(ing.as_str())).count()
+    let knowledge_base = AllergenKnowledgeBase::from_food_items(food_items);
+    knowledge_base.safe_ingredients().into_iter().map(|(_, count)| count).sum()
 }
 
 pub fn part_2(food_items: &[FoodItem]) -> String {
-    let mut allergens_possibilities = calc_initial_allergens_possibilities(food_items);
-    let mut ingredients_and_their_alergens = Vec::new();
-    while let Some((allergen, ingredient)) = allergens_possibilities.iter().find_map(|(allergen, ingredients)| {
-        let mut it = ingredients.iter();
-        let ingredient = it.next()?;
-        if it.next().is_some() {
-            None
-        } else {
-            Some((*allergen, *ingredient))
-        }
-    }) {
-        ingredients_and_their_alergens.push((ingredient, allergen));
-        allergens_possibilities.remove(allergen);
-        for ingredients in allergens_possibilities.values_mut() {
-            ingredients.remove(ingredient);
-        }
+    let knowledge_base = AllergenKnowledgeBase::from_food_items(food_items);
+    match knowledge_base.solve() {
+        AllergenSolution::Solved(assignment) => {
+            assignment.into_iter().map(|(_, ingredient)| ingredient).collect::<Vec<&str>>().join(",")
+        },
+        AllergenSolution::Ambiguous(ambiguous) => panic!("Ambiguous allergens: {:?}", ambiguous),
+        AllergenSolution::Contradiction(unmatched) => panic!("No ingredient can contain {:?}", unmatched),
     }
-    ingredients_and_their_alergens.sort_by_key(|&(_, alg)| alg);
-    ingredients_and_their_alergens.into_iter().map(|(ing, _)| ing).collect::<Vec<&str>>().join(",")
 }
@@ -1,144 +1,528 @@
 use std::collections::VecDeque;
 
-use hashbrown::HashSet;
+use hashbrown::{HashMap, HashSet};
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct Game {
-    decks: [VecDeque<usize>; 2],
+    decks: Vec<VecDeque<usize>>,
+}
+
+impl Game {
+    pub fn decks(&self) -> &[VecDeque<usize>] {
+        &self.decks
+    }
 }
 
 pub fn generator(input: &str) -> Game {
-    let mut it = input.lines();
+    let mut it = input.lines().peekable();
+    let mut decks = Vec::new();
+
+    while let Some(header) = it.next() {
+        assert!(header == format!("Player {}:", decks.len() + 1), "Expected header for player {}, got {:?}", decks.len() + 1, header);
+        decks.push(it.by_ref()
+            .take_while(|&line| !line.is_empty())
+            .map(|line| line.parse().unwrap())
+            .collect());
+        while it.peek() == Some(&"") {
+            it.next();
+        }
+    }
+    assert!(2 <= decks.len(), "Need at least two players");
 
-    assert!(it.next() == Some("Player 1:"));
-    let first_player_cards = it.by_ref()
-        .take_while(|&line| line != "")
-        .map(|line| line.parse().unwrap())
-        .collect();
+    Game { decks }
+}
 
-    assert!(it.next() == Some("Player 2:"));
-    let second_player_cards = it.by_ref()
-        .take_while(|&line| line != "")
-        .map(|line| line.parse().unwrap())
-        .collect();
+// The index of the winning player, starting from 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Winner(pub usize);
 
-    Game { decks: [
-        first_player_cards,
-        second_player_cards,
-    ]}
+impl Winner {
+    pub fn player_number(&self) -> usize {
+        self.0 + 1
+    }
 }
 
-#[derive(Debug)]
-enum Winner {
-    FirstPlayer,
-    SecondPlayer,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DrawnCard {
+    pub player: usize,
+    pub card: usize,
 }
 
-impl Game {
-    fn draw_cards(&mut self) -> [usize; 2] {
-        [
-            self.decks[0].pop_front().unwrap(),
-            self.decks[1].pop_front().unwrap(),
-        ]
-    }
-    fn play_round(&mut self, winning_rule: impl FnOnce(&mut Self, [usize; 2]) -> Winner) {
-        let cards = self.draw_cards();
-        assert!(cards[0] != cards[1]);
-        let winner = winning_rule(self, cards);
-        self.push_cards_back(winner, cards);
-    }
-
-    fn push_cards_back(&mut self, winner: Winner, drawn_cards: [usize; 2]) {
-        match winner {
-            Winner::FirstPlayer => {
-                self.decks[0].push_back(drawn_cards[0]);
-                self.decks[0].push_back(drawn_cards[1]);
-            },
-            Winner::SecondPlayer => {
-                self.decks[1].push_back(drawn_cards[1]);
-                self.decks[1].push_back(drawn_cards[0]);
-            },
+pub trait CombatRules {
+    // Decide a round that is not decided by a subgame
+    fn round_winner(&self, drawn_cards: &[DrawnCard]) -> Winner;
+
+    // The order in which the drawn cards are placed at the bottom of the winner's deck
+    fn cards_for_winner(&self, winner: Winner, drawn_cards: &[DrawnCard]) -> Vec<usize>;
+
+    // `game` is after the cards were drawn
+    fn should_recurse(&self, game: &Game, drawn_cards: &[DrawnCard]) -> bool;
+
+    fn repetition_winner(&self) -> Winner {
+        Winner(0)
+    }
+
+    // Allows deciding a subgame without playing it
+    fn subgame_shortcut(&self, _subgame: &Game) -> Option<Winner> {
+        None
+    }
+}
+
+fn highest_card_wins(drawn_cards: &[DrawnCard]) -> Winner {
+    let highest = drawn_cards.iter().max_by_key(|drawn| drawn.card).unwrap();
+    Winner(highest.player)
+}
+
+// The winner's card first, and then the other cards from highest to lowest
+fn winner_card_first(winner: Winner, drawn_cards: &[DrawnCard]) -> Vec<usize> {
+    let mut cards: Vec<&DrawnCard> = drawn_cards.iter().collect();
+    cards.sort_by_key(|drawn| (drawn.player != winner.0, std::cmp::Reverse(drawn.card)));
+    cards.into_iter().map(|drawn| drawn.card).collect()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ClassicRules;
+
+impl CombatRules for ClassicRules {
+    fn round_winner(&self, drawn_cards: &[DrawnCard]) -> Winner {
+        highest_card_wins(drawn_cards)
+    }
+
+    fn cards_for_winner(&self, winner: Winner, drawn_cards: &[DrawnCard]) -> Vec<usize> {
+        winner_card_first(winner, drawn_cards)
+    }
+
+    fn should_recurse(&self, _game: &Game, _drawn_cards: &[DrawnCard]) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecursiveRules;
+
+impl CombatRules for RecursiveRules {
+    fn round_winner(&self, drawn_cards: &[DrawnCard]) -> Winner {
+        highest_card_wins(drawn_cards)
+    }
+
+    fn cards_for_winner(&self, winner: Winner, drawn_cards: &[DrawnCard]) -> Vec<usize> {
+        winner_card_first(winner, drawn_cards)
+    }
+
+    fn should_recurse(&self, game: &Game, drawn_cards: &[DrawnCard]) -> bool {
+        drawn_cards.iter().all(|drawn| game.decks[drawn.player].len() >= drawn.card)
+    }
+
+    // If the first player holds the highest card, it is at least the number of cards in the
+    // subgame so it can never trigger a deeper recursion - which means the first player can
+    // never lose it, and the subgame must end in their favor.
+    fn subgame_shortcut(&self, subgame: &Game) -> Option<Winner> {
+        let highest_card_of_first_player = subgame.decks[0].iter().max();
+        if subgame.decks[1..].iter().flatten().all(|card| Some(card) < highest_card_of_first_player) {
+            Some(Winner(0))
+        } else {
+            None
         }
     }
+}
+
+impl Game {
+    // Players that ran out of cards don't draw
+    fn draw_cards(&mut self) -> Vec<DrawnCard> {
+        self.decks.iter_mut().enumerate().filter_map(|(player, deck)| {
+            Some(DrawnCard { player, card: deck.pop_front()? })
+        }).collect()
+    }
+
+    fn push_cards_back(&mut self, winner: Winner, cards: &[usize]) {
+        self.decks[winner.0].extend(cards.iter().copied());
+    }
 
     fn game_winner(&self) -> Option<Winner> {
-        if self.decks[0].is_empty() {
-            Some(Winner::SecondPlayer)
-        } else if self.decks[1].is_empty() {
-            Some(Winner::FirstPlayer)
+        let mut players_with_cards = self.decks.iter().enumerate().filter(|(_, deck)| !deck.is_empty());
+        let (winner, _) = players_with_cards.next()?;
+        if players_with_cards.next().is_none() {
+            Some(Winner(winner))
         } else {
             None
         }
     }
 
     fn calc_score(&self, winner: Winner) -> usize {
-        let winner_cards = &self.decks[match winner {
-            Winner::FirstPlayer => 0,
-            Winner::SecondPlayer => 1,
-        }];
+        let winner_cards = &self.decks[winner.0];
         (1..).zip(winner_cards.iter().rev()).map(|(i, &card)| i * card).sum()
     }
 }
 
-pub fn part_1(game: &Game) -> usize {
+pub fn play_classic(game: &Game, rules: &impl CombatRules) -> (Winner, usize) {
     let mut game = game.clone();
     loop {
-        game.play_round(|_this, [first_player_card, second_player_card]| {
-            if first_player_card < second_player_card {
-                Winner::SecondPlayer
-            } else {
-                Winner::FirstPlayer
-            }
-        });
+        let drawn_cards = game.draw_cards();
+        let winner = rules.round_winner(&drawn_cards);
+        game.push_cards_back(winner, &rules.cards_for_winner(winner, &drawn_cards));
         if let Some(winner) = game.game_winner() {
-            return game.calc_score(winner);
+            return (winner, game.calc_score(winner));
         }
     }
 }
 
+pub fn part_1(game: &Game) -> usize {
+    let (_, score) = play_classic(game, &ClassicRules);
+    score
+}
+
 impl Game {
-    fn subgame(&self, cards_to_take: [usize; 2]) -> Game {
-        Game {
-            decks: [
-                self.decks[0].iter().take(cards_to_take[0]).copied().collect(),
-                self.decks[1].iter().take(cards_to_take[1]).copied().collect(),
-            ],
+    // Players that did not draw a card don't participate in the subgame
+    fn subgame(&self, drawn_cards: &[DrawnCard]) -> Game {
+        let mut decks = vec![VecDeque::new(); self.decks.len()];
+        for drawn in drawn_cards.iter() {
+            decks[drawn.player] = self.decks[drawn.player].iter().take(drawn.card).copied().collect();
         }
+        Game { decks }
     }
 
-    fn recursive_play(&mut self) -> Winner {
+    // Cards are written as LEB128 varints, and all decks but the last are prefixed with their length
+    fn state_key(&self) -> Vec<u8> {
+        fn push_varint(key: &mut Vec<u8>, mut value: usize) {
+            while 0x80 <= value {
+                key.push((value & 0x7f) as u8 | 0x80);
+                value >>= 7;
+            }
+            key.push(value as u8);
+        }
+
+        let mut key = Vec::with_capacity(self.decks.iter().map(|deck| deck.len() + 1).sum());
+        for deck in self.decks[..self.decks.len() - 1].iter() {
+            push_varint(&mut key, deck.len());
+        }
+        for &card in self.decks.iter().flatten() {
+            push_varint(&mut key, card);
+        }
+        key
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct RecursiveCombatStats {
+    pub rounds: usize,
+    pub subgames: usize,
+    pub memoized_subgames: usize,
+    pub shortcut_subgames: usize,
+    pub max_depth: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    Memoized,
+    Shortcut,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CombatEvent {
+    GameStarted { game: usize },
+    RoundStarted { game: usize, round: usize },
+    CardsDrawn { cards: Vec<DrawnCard> },
+    SubgameEntered { game: usize },
+    SubgameSkipped { reason: SkipReason, winner: Winner },
+    SubgameExited { game: usize },
+    RepeatedState { game: usize },
+    // `cards` are in the order they were placed at the bottom of the winner's deck
+    RoundWon { game: usize, round: usize, winner: Winner, cards: Vec<usize> },
+    GameWon { game: usize, winner: Winner },
+}
+
+#[derive(Debug)]
+pub struct RecursiveCombat<R> {
+    rules: R,
+    shortcuts: bool,
+    log: Option<Vec<CombatEvent>>,
+    subgame_winners: HashMap<Vec<u8>, Winner>,
+    games_started: usize,
+    stats: RecursiveCombatStats,
+}
+
+impl<R: CombatRules> RecursiveCombat<R> {
+    pub fn new(rules: R) -> Self {
+        Self {
+            rules,
+            shortcuts: true,
+            log: None,
+            subgame_winners: HashMap::new(),
+            games_started: 0,
+            stats: RecursiveCombatStats::default(),
+        }
+    }
+
+    // Play every subgame in full, so that the log matches the puzzle's transcript
+    pub fn exhaustive(self) -> Self {
+        Self {
+            shortcuts: false,
+            ..self
+        }
+    }
+
+    pub fn with_log(self) -> Self {
+        Self {
+            log: Some(Vec::new()),
+            ..self
+        }
+    }
+
+    pub fn stats(&self) -> &RecursiveCombatStats {
+        &self.stats
+    }
+
+    pub fn log(&self) -> Option<&[CombatEvent]> {
+        self.log.as_deref()
+    }
+
+    fn emit(&mut self, event: CombatEvent) {
+        if let Some(log) = self.log.as_mut() {
+            log.push(event);
+        }
+    }
+
+    // Returns the winner and their score
+    pub fn play_game(&mut self, game: &Game) -> (Winner, usize) {
+        let mut game = game.clone();
+        let winner = self.play(&mut game, 0);
+        (winner, game.calc_score(winner))
+    }
+
+    fn play(&mut self, game: &mut Game, depth: usize) -> Winner {
+        self.stats.max_depth = self.stats.max_depth.max(depth);
+        self.games_started += 1;
+        let game_number = self.games_started;
+        self.emit(CombatEvent::GameStarted { game: game_number });
         let mut past_rounds = HashSet::new();
 
-        loop {
-            if !past_rounds.insert(self.clone()) {
-                return Winner::FirstPlayer;
+        for round in 1.. {
+            if !past_rounds.insert(game.state_key()) {
+                let winner = self.rules.repetition_winner();
+                self.emit(CombatEvent::RepeatedState { game: game_number });
+                self.emit(CombatEvent::GameWon { game: game_number, winner });
+                return winner;
             }
 
-            self.play_round(|this, drawn_cards| {
-                if drawn_cards.iter().zip(this.decks.iter()).all(|(&drawn, deck)| deck.len() >= drawn) {
-                    let mut subgame = this.subgame(drawn_cards);
-                    subgame.recursive_play()
+            self.stats.rounds += 1;
+            self.emit(CombatEvent::RoundStarted { game: game_number, round });
+            let drawn_cards = game.draw_cards();
+            self.emit(CombatEvent::CardsDrawn { cards: drawn_cards.clone() });
+            let winner = if self.rules.should_recurse(game, &drawn_cards) {
+                let subgame = game.subgame(&drawn_cards);
+                self.play_subgame(subgame, game_number, depth + 1)
+            } else {
+                self.rules.round_winner(&drawn_cards)
+            };
+            let cards = self.rules.cards_for_winner(winner, &drawn_cards);
+            game.push_cards_back(winner, &cards);
+            self.emit(CombatEvent::RoundWon { game: game_number, round, winner, cards });
+
+            if let Some(winner) = game.game_winner() {
+                self.emit(CombatEvent::GameWon { game: game_number, winner });
+                return winner;
+            }
+        }
+        unreachable!()
+    }
+
+    fn play_subgame(&mut self, mut subgame: Game, parent_game: usize, depth: usize) -> Winner {
+        self.stats.subgames += 1;
+
+        if self.shortcuts {
+            if let Some(winner) = self.rules.subgame_shortcut(&subgame) {
+                self.stats.shortcut_subgames += 1;
+                self.emit(CombatEvent::SubgameSkipped { reason: SkipReason::Shortcut, winner });
+                return winner;
+            }
+        }
+
+        let key = subgame.state_key();
+        if self.shortcuts {
+            if let Some(&winner) = self.subgame_winners.get(&key) {
+                self.stats.memoized_subgames += 1;
+                self.emit(CombatEvent::SubgameSkipped { reason: SkipReason::Memoized, winner });
+                return winner;
+            }
+        }
+        self.emit(CombatEvent::SubgameEntered { game: self.games_started + 1 });
+        let winner = self.play(&mut subgame, depth);
+        self.emit(CombatEvent::SubgameExited { game: parent_game });
+        if self.shortcuts {
+            self.subgame_winners.insert(key, winner);
+        }
+        winner
+    }
+}
+
+pub fn recursive_combat(game: &Game) -> (usize, RecursiveCombatStats) {
+    let mut combat = RecursiveCombat::new(RecursiveRules);
+    let (_, score) = combat.play_game(game);
+    (score, combat.stats)
+}
+
+#[derive(Debug, Clone)]
+struct ReplayFrame {
+    game_number: usize,
+    round: usize,
+    game: Game,
+    drawn_cards: Option<Vec<DrawnCard>>,
+}
+
+// Reconstructs the decks by applying a log produced by `RecursiveCombat` to the initial game
+#[derive(Debug, Clone)]
+pub struct Replay<'a> {
+    initial: &'a Game,
+    stack: Vec<ReplayFrame>,
+}
+
+impl<'a> Replay<'a> {
+    pub fn new(initial: &'a Game) -> Self {
+        Self {
+            initial,
+            stack: Vec::new(),
+        }
+    }
+
+    // The game number, round number and decks of the innermost game being played
+    pub fn current(&self) -> Option<(usize, usize, &Game)> {
+        let frame = self.stack.last()?;
+        Some((frame.game_number, frame.round, &frame.game))
+    }
+
+    pub fn apply(&mut self, event: &CombatEvent) {
+        match event {
+            &CombatEvent::GameStarted { game } => {
+                if self.stack.is_empty() {
+                    self.stack.push(ReplayFrame {
+                        game_number: game,
+                        round: 0,
+                        game: self.initial.clone(),
+                        drawn_cards: None,
+                    });
                 } else {
-                    let [first_player_card, second_player_card] = drawn_cards;
-                    if first_player_card < second_player_card {
-                        Winner::SecondPlayer
-                    } else {
-                        Winner::FirstPlayer
-                    }
+                    self.stack.last_mut().unwrap().game_number = game;
                 }
-            });
+            },
+            &CombatEvent::RoundStarted { round, .. } => {
+                self.top().round = round;
+            },
+            CombatEvent::CardsDrawn { cards } => {
+                let frame = self.top();
+                assert!(&frame.game.draw_cards() == cards, "Replayed cards do not match the log");
+                frame.drawn_cards = Some(cards.clone());
+            },
+            &CombatEvent::SubgameEntered { game } => {
+                let frame = self.top();
+                let subgame = frame.game.subgame(frame.drawn_cards.as_ref().expect("Subgame entered before drawing cards"));
+                self.stack.push(ReplayFrame {
+                    game_number: game,
+                    round: 0,
+                    game: subgame,
+                    drawn_cards: None,
+                });
+            },
+            CombatEvent::SubgameExited { .. } => {
+                self.stack.pop();
+            },
+            CombatEvent::RoundWon { winner, cards, .. } => {
+                let frame = self.top();
+                frame.drawn_cards.take().expect("Round won before drawing cards");
+                frame.game.push_cards_back(*winner, cards);
+            },
+            CombatEvent::SubgameSkipped { .. } | CombatEvent::RepeatedState { .. } | CombatEvent::GameWon { .. } => {
+            },
+        }
+    }
 
-            if let Some(winner) = self.game_winner() {
-                return winner;
+    fn top(&mut self) -> &mut ReplayFrame {
+        self.stack.last_mut().expect("Event applied before the game started")
+    }
+}
+
+// The decks at the start of the given round of the given game
+pub fn decks_at_round(initial: &Game, log: &[CombatEvent], game: usize, round: usize) -> Option<Game> {
+    let mut replay = Replay::new(initial);
+    for event in log.iter() {
+        replay.apply(event);
+        if *event == (CombatEvent::RoundStarted { game, round }) {
+            return replay.current().map(|(_, _, decks)| decks.clone());
+        }
+    }
+    None
+}
+
+pub struct Transcript<'a> {
+    pub initial: &'a Game,
+    pub log: &'a [CombatEvent],
+}
+
+impl Game {
+    fn fmt_decks(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for (player, deck) in self.decks.iter().enumerate() {
+            writeln!(f, "Player {}'s deck: {}", player + 1, deck.iter().map(|card| card.to_string()).collect::<Vec<_>>().join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl core::fmt::Display for Transcript<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let mut replay = Replay::new(self.initial);
+        for event in self.log.iter() {
+            replay.apply(event);
+            match event {
+                CombatEvent::GameStarted { game } => {
+                    writeln!(f, "=== Game {} ===", game)?;
+                },
+                CombatEvent::RoundStarted { game, round } => {
+                    writeln!(f)?;
+                    writeln!(f, "-- Round {} (Game {}) --", round, game)?;
+                    replay.current().unwrap().2.fmt_decks(f)?;
+                },
+                CombatEvent::CardsDrawn { cards } => {
+                    for drawn in cards.iter() {
+                        writeln!(f, "Player {} plays: {}", drawn.player + 1, drawn.card)?;
+                    }
+                },
+                CombatEvent::SubgameEntered { .. } => {
+                    writeln!(f, "Playing a sub-game to determine the winner...")?;
+                    writeln!(f)?;
+                },
+                CombatEvent::SubgameSkipped { reason, winner } => {
+                    writeln!(f, "Playing a sub-game to determine the winner...")?;
+                    match reason {
+                        SkipReason::Memoized => writeln!(f, "(this sub-game was already played - player {} won it)", winner.player_number())?,
+                        SkipReason::Shortcut => writeln!(f, "(player {} is certain to win this sub-game)", winner.player_number())?,
+                    }
+                },
+                CombatEvent::SubgameExited { game } => {
+                    writeln!(f)?;
+                    writeln!(f, "...anyway, back to game {}.", game)?;
+                },
+                CombatEvent::RepeatedState { game } => {
+                    writeln!(f)?;
+                    writeln!(f, "The decks repeat an earlier round of game {}!", game)?;
+                },
+                CombatEvent::RoundWon { game, round, winner, .. } => {
+                    writeln!(f, "Player {} wins round {} of game {}!", winner.player_number(), round, game)?;
+                },
+                CombatEvent::GameWon { game, winner } => {
+                    writeln!(f, "The winner of game {} is player {}!", game, winner.player_number())?;
+                },
             }
-            // break;
         }
+        if let Some((_, _, game)) = replay.current() {
+            writeln!(f)?;
+            writeln!(f)?;
+            writeln!(f, "== Post-game results ==")?;
+            game.fmt_decks(f)?;
+        }
+        Ok(())
     }
 }
 
 pub fn part_2(game: &Game) -> usize {
-    let mut game = game.clone();
-    let winner = game.recursive_play();
-    println!("{:?}", winner);
-    game.calc_score(winner)
+    let (_, score) = RecursiveCombat::new(RecursiveRules).play_game(game);
+    score
 }
@@ -1,25 +1,118 @@
-#[derive(Debug, Clone)]
-pub struct GameState {
-    nexts: Vec<usize>,
+#[derive(Debug)]
+pub enum ParseError {
+    Empty,
+    InvalidLabel(String),
+    NotAPermutation {
+        cups: usize,
+        duplicates: Vec<u32>,
+        missing: Vec<u32>,
+    },
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "No cup labels"),
+            ParseError::InvalidLabel(label) => write!(f, "Invalid cup label {:?}", label),
+            ParseError::NotAPermutation { cups, duplicates, missing } => {
+                write!(f, "Labels are not a permutation of 1..={}", cups)?;
+                if !duplicates.is_empty() {
+                    write!(f, " - duplicate labels {:?}", duplicates)?;
+                }
+                if !missing.is_empty() {
+                    write!(f, " - missing labels {:?}", missing)?;
+                }
+                Ok(())
+            },
+        }
+    }
 }
 
-pub fn generator(input: &str) -> GameState {
-    let mut nexts = vec![0; 10];
-    let mut current = 0;
-    for c in input.chars() {
-        let label = c as usize - '0' as usize;
-        nexts[current] = label;
-        current = label;
+// Labels are comma separated. The puzzle's format, where each digit is a label, is also accepted
+pub fn parse(input: &str) -> Result<Vec<u32>, ParseError> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err(ParseError::Empty);
+    }
+    let labels: Vec<u32> = if input.contains(',') {
+        input.split(',').map(|label| {
+            let label = label.trim();
+            match label.parse() {
+                Ok(0) | Err(_) => Err(ParseError::InvalidLabel(label.to_owned())),
+                Ok(label) => Ok(label),
+            }
+        }).collect::<Result<_, _>>()?
+    } else {
+        input.chars().map(|c| match c.to_digit(10) {
+            Some(0) | None => Err(ParseError::InvalidLabel(c.to_string())),
+            Some(label) => Ok(label),
+        }).collect::<Result<_, _>>()?
+    };
+
+    let mut seen = vec![false; labels.len() + 1];
+    let mut duplicates = Vec::new();
+    for &label in labels.iter() {
+        if let Some(seen) = seen.get_mut(label as usize) {
+            if *seen {
+                duplicates.push(label);
+            }
+            *seen = true;
+        }
+    }
+    let missing: Vec<u32> = (1..=labels.len() as u32).filter(|&label| !seen[label as usize]).collect();
+    if !duplicates.is_empty() || !missing.is_empty() {
+        duplicates.sort();
+        duplicates.dedup();
+        return Err(ParseError::NotAPermutation { cups: labels.len(), duplicates, missing });
     }
-    nexts[current] = nexts[0];
-    GameState { nexts }
+    Ok(labels)
+}
+
+pub fn generator(input: &str) -> Vec<u32> {
+    parse(input).unwrap_or_else(|err| panic!("{}", err))
+}
+
+const PROGRESS_INTERVAL: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+pub struct CupGame {
+    // nexts[label] is the label of the cup after it, and nexts[0] is the current cup
+    nexts: Vec<u32>,
+    pickup: usize,
 }
 
-impl GameState {
-    fn iter_from(&self, start_from: usize) -> impl Iterator<Item = usize> + '_ {
-        let first = self.nexts[start_from];
+impl CupGame {
+    // The cups that don't appear in `labels` are placed after them in increasing order
+    pub fn new(labels: &[u32], total_cups: u32, pickup: usize) -> Self {
+        assert!(labels.len() <= total_cups as usize, "More labels than cups");
+        assert!(1 <= pickup, "Must pick up at least one cup");
+        assert!(pickup + 2 <= total_cups as usize, "Not enough cups to pick up {}", pickup);
+        let mut nexts = vec![0; total_cups as usize + 1];
+        let mut current = 0;
+        for label in labels.iter().copied().chain(labels.len() as u32 + 1..=total_cups) {
+            nexts[current as usize] = label;
+            current = label;
+        }
+        nexts[current as usize] = nexts[0];
+        Self { nexts, pickup }
+    }
+
+    pub fn total_cups(&self) -> u32 {
+        self.nexts.len() as u32 - 1
+    }
+
+    pub fn current_cup(&self) -> u32 {
+        self.nexts[0]
+    }
+
+    fn next(&self, label: u32) -> u32 {
+        self.nexts[label as usize]
+    }
+
+    fn iter_from(&self, start_from: u32) -> impl Iterator<Item = u32> + '_ {
+        let first = self.next(start_from);
         std::iter::successors(Some(first), move |&cup| {
-            let next = self.nexts[cup];
+            let next = self.next(cup);
             if next == first {
                 None
             } else {
@@ -27,9 +120,13 @@ impl GameState {
             }
         })
     }
+
+    pub fn cups_after(&self, label: u32, k: usize) -> Vec<u32> {
+        self.iter_from(label).take(k).collect()
+    }
 }
 
-impl std::fmt::Display for GameState {
+impl std::fmt::Display for CupGame {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut it = self.iter_from(0);
         write!(f, "({})", it.next().unwrap())?;
@@ -40,54 +137,55 @@ impl std::fmt::Display for GameState {
     }
 }
 
-impl GameState {
+impl CupGame {
     fn run_move(&mut self) {
-        let mut pick_up: [usize; 3] = [0, 0, 0];
-        for (c, p) in self.iter_from(0).skip(1).zip(pick_up.iter_mut()) {
-            *p = c;
+        let current = self.current_cup();
+        let first_picked = self.next(current);
+        let mut last_picked = first_picked;
+        for _ in 1..self.pickup {
+            last_picked = self.next(last_picked);
         }
-        let mut put_after = self.nexts[0] - 1;
+
+        let is_picked = |label: u32| {
+            std::iter::successors(Some(first_picked), |&cup| Some(self.next(cup))).take(self.pickup).any(|cup| cup == label)
+        };
+        let mut put_after = current;
         loop {
-            if put_after < 1 {
-                put_after = self.nexts.len() - 1;
-            }
-            if pick_up.iter().any(|&c| c == put_after) {
-                put_after -= 1;
+            put_after = if put_after <= 1 {
+                self.total_cups()
             } else {
+                put_after - 1
+            };
+            if !is_picked(put_after) {
                 break;
             }
         }
-        let first_number = self.nexts[0];
-        self.nexts[first_number] = self.nexts[pick_up[2]];
-        self.nexts[pick_up[2]] = self.nexts[put_after];
-        self.nexts[put_after] = pick_up[0];
-        self.nexts[0] = self.nexts[self.nexts[0]];
+
+        self.nexts[current as usize] = self.next(last_picked);
+        self.nexts[last_picked as usize] = self.next(put_after);
+        self.nexts[put_after as usize] = first_picked;
+        self.nexts[0] = self.next(current);
     }
-}
 
-pub fn part_1(game: &GameState) -> String {
-    let mut game = game.clone();
-    for _ in 0..100 {
-        game.run_move();
+    pub fn run_moves(&mut self, moves: usize, mut progress: impl FnMut(usize, usize)) {
+        for move_number in 1..=moves {
+            self.run_move();
+            if move_number % PROGRESS_INTERVAL == 0 {
+                progress(move_number, moves);
+            }
+        }
+        progress(moves, moves);
     }
-    game.iter_from(1).take_while(|&cup| cup != 1).map(|cup| format!("{}", cup)).collect::<Vec<_>>().join("")
 }
 
-pub fn part_2(game: &GameState) -> usize {
-    let mut game = game.clone();
-    let last_cup = game.iter_from(0).last().unwrap();
-    game.nexts[last_cup] = game.nexts.len();
-    let goal = 1_000_000;
-    game.nexts.extend((game.nexts.len() + 1)..=goal);
-    game.nexts.push(game.nexts[0]);
-
-    for _ in 0..10_000_000 {
-        game.run_move();
-    }
+pub fn part_1(labels: &[u32]) -> String {
+    let mut game = CupGame::new(labels, labels.len() as u32, 3);
+    game.run_moves(100, |_, _| {});
+    game.cups_after(1, labels.len() - 1).into_iter().map(|cup| format!("{}", cup)).collect::<Vec<_>>().join("")
+}
 
-    if let [a, b] = game.iter_from(1).take(2).collect::<Vec<_>>().as_slice() {
-        a * b
-    } else {
-        panic!("Expected to take 2");
-    }
+pub fn part_2(labels: &[u32]) -> u64 {
+    let mut game = CupGame::new(labels, 1_000_000, 3);
+    game.run_moves(10_000_000, |_, _| {});
+    game.cups_after(1, 2).into_iter().map(u64::from).product()
 }
@@ -1,14 +1,7 @@
 use hashbrown::{HashSet, HashMap};
 
-#[derive(Debug)]
-pub enum HexDirection {
-    E,
-    SE,
-    SW,
-    W,
-    NW,
-    NE,
-}
+use crate::hex::Axial;
+pub use crate::hex::Direction as HexDirection;
 
 #[derive(Debug)]
 pub struct Directions {
@@ -44,24 +37,11 @@ pub fn generator(input: &str) -> Vec<Directions> {
     }).collect()
 }
 
-type Coord = [isize; 2];
-
-impl HexDirection {
-    fn apply_to_coord(&self, [x, y]: Coord) -> Coord {
-        match self {
-            HexDirection::E => [x + 2, y],
-            HexDirection::SE => [x + 1, y - 1],
-            HexDirection::SW => [x - 1, y - 1],
-            HexDirection::W => [x - 2, y],
-            HexDirection::NW => [x - 1, y + 1],
-            HexDirection::NE => [x + 1, y + 1],
-        }
-    }
-}
+type Coord = Axial;
 
 impl Directions {
     fn resolve_from_origin_tile(&self, origin: Coord) -> Coord {
-        self.hexdirs.iter().fold(origin, |coord, hd| hd.apply_to_coord(coord))
+        self.hexdirs.iter().fold(origin, |coord, &hd| coord.neighbor(hd))
     }
 }
 
@@ -69,7 +49,7 @@ pub fn black_tiles_from_input(input: &[Directions]) -> HashSet<Coord> {
     let mut black_tiles = HashMap::new();
 
     for directions in input.iter() {
-        let tile_to_flip = directions.resolve_from_origin_tile([0 ,0]);
+        let tile_to_flip = directions.resolve_from_origin_tile(Axial::ORIGIN);
         match black_tiles.entry(tile_to_flip) {
             hashbrown::hash_map::Entry::Occupied(entry) => {
                 entry.remove_entry();
@@ -87,48 +67,121 @@ pub fn part_1(input: &[Directions]) -> usize {
     black_tiles_from_input(input).len()
 }
 
-impl HexDirection {
-    fn all_directions() -> [Self; 6] {
-        [
-            Self::E,
-            Self::SE,
-            Self::SW,
-            Self::W,
-            Self::NW,
-            Self::NE,
-        ]
+fn next_day(black_tiles: &HashSet<Coord>) -> HashSet<Coord> {
+    // The bool says if it already had a black tile before
+    let mut neighbors_map: HashMap<Coord, (bool, usize)> = black_tiles.iter()
+        .map(|&coord| (coord, (true, 0)))
+        .collect();
+    for &black_tile in black_tiles.iter() {
+        for &neighbor in black_tile.neighbors().iter() {
+            neighbors_map.entry(neighbor).or_insert((false, 0)).1 += 1;
+        }
     }
+    neighbors_map.into_iter().filter_map(|(coord, (was_black, num_neighbors))| {
+        if was_black {
+            if num_neighbors == 0 || 2 < num_neighbors {
+                None
+            } else {
+                Some(coord)
+            }
+        } else {
+            if num_neighbors == 2 {
+                Some(coord)
+            } else {
+                None
+            }
+        }
+    }).collect()
 }
 
-pub fn part_2(input: &[Directions]) -> usize {
-    let mut black_tiles = black_tiles_from_input(input);
-
-    for _ in 0..100 {
-        // The bool says if it already had a black tile before
-        let mut neighbors_map: HashMap<Coord, (bool, usize)> = black_tiles.iter()
-            .map(|&coord| (coord, (true, 0)))
-            .collect();
-        for &black_tile in black_tiles.iter() {
-            for direction in HexDirection::all_directions().iter() {
-                neighbors_map.entry(direction.apply_to_coord(black_tile)).or_insert((false, 0)).1 += 1;
+#[derive(Debug)]
+pub struct LobbyHistory {
+    // Index 0 is the layout from the input, before any day passed
+    days: Vec<HashSet<Coord>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct GenerationStats {
+    pub day: usize,
+    pub black_tiles: usize,
+    // Distance of the farthest black tile from the reference tile
+    pub bounding_radius: usize,
+    // Fraction of the tiles within the bounding radius that are black
+    pub density: f64,
+}
+
+pub fn lobby_history(input: &[Directions], days: usize) -> LobbyHistory {
+    let mut history = vec![black_tiles_from_input(input)];
+    for _ in 0..days {
+        let next = next_day(history.last().unwrap());
+        history.push(next);
+    }
+    LobbyHistory { days: history }
+}
+
+impl LobbyHistory {
+    pub fn last_day(&self) -> usize {
+        self.days.len() - 1
+    }
+
+    pub fn black_tiles(&self, day: usize) -> &HashSet<Coord> {
+        &self.days[day]
+    }
+
+    pub fn black_tile_counts(&self) -> Vec<usize> {
+        self.days.iter().map(|black_tiles| black_tiles.len()).collect()
+    }
+
+    pub fn growth_summary(&self) -> Vec<GenerationStats> {
+        self.days.iter().enumerate().map(|(day, black_tiles)| {
+            let bounding_radius = black_tiles.iter().map(|tile| tile.length()).max().unwrap_or(0);
+            let tiles_in_radius = 3 * bounding_radius * (bounding_radius + 1) + 1;
+            GenerationStats {
+                day,
+                black_tiles: black_tiles.len(),
+                bounding_radius,
+                density: black_tiles.len() as f64 / tiles_in_radius as f64,
             }
-        }
-        black_tiles = neighbors_map.into_iter().filter_map(|(coord, (was_black, num_neighbors))| {
-            if was_black {
-                if num_neighbors == 0 || 2 < num_neighbors {
-                    None
-                } else {
-                    Some(coord)
-                }
-            } else {
-                if num_neighbors == 2 {
-                    Some(coord)
-                } else {
-                    None
-                }
+        }).collect()
+    }
+
+    // Each chosen day is drawn in its own panel, side by side, all centered on the reference tile
+    pub fn to_svg(&self, days: &[usize]) -> String {
+        use std::fmt::Write;
+
+        const SIZE: f64 = 5.0;
+        const LABEL_HEIGHT: f64 = 20.0;
+        let sqrt3 = 3f64.sqrt();
+        let radius = days.iter().flat_map(|&day| self.days[day].iter()).map(|tile| tile.length()).max().unwrap_or(0) as f64;
+        let panel_width = sqrt3 * SIZE * (2.0 * radius + 1.0) + 2.0 * SIZE;
+        let panel_height = SIZE * (3.0 * radius + 2.0) + 2.0 * SIZE;
+
+        let mut svg = String::new();
+        writeln!(svg, r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.1}" height="{:.1}">"#, panel_width * days.len() as f64, panel_height + LABEL_HEIGHT).unwrap();
+        writeln!(svg, r#"<rect width="100%" height="100%" fill="white"/>"#).unwrap();
+        for (i, &day) in days.iter().enumerate() {
+            let center_x = panel_width * (i as f64 + 0.5);
+            let center_y = LABEL_HEIGHT + panel_height / 2.0;
+            writeln!(svg, r#"<g>"#).unwrap();
+            writeln!(svg, r#"<text x="{:.1}" y="{:.1}" text-anchor="middle" font-family="monospace">Day {}: {} black</text>"#, center_x, LABEL_HEIGHT * 0.75, day, self.days[day].len()).unwrap();
+            let mut tiles: Vec<&Coord> = self.days[day].iter().collect();
+            tiles.sort();
+            for tile in tiles {
+                let x = center_x + SIZE * sqrt3 * (tile.q as f64 + tile.r as f64 / 2.0);
+                let y = center_y + SIZE * 1.5 * tile.r as f64;
+                let corners: Vec<String> = (0..6).map(|corner| {
+                    let angle = std::f64::consts::PI / 180.0 * (60.0 * corner as f64 - 30.0);
+                    format!("{:.2},{:.2}", x + SIZE * angle.cos(), y + SIZE * angle.sin())
+                }).collect();
+                writeln!(svg, r#"<polygon points="{}" fill="black" stroke="gray" stroke-width="0.5"/>"#, corners.join(" ")).unwrap();
             }
-        }).collect();
+            writeln!(svg, r#"</g>"#).unwrap();
+        }
+        writeln!(svg, r#"</svg>"#).unwrap();
+        svg
     }
+}
 
-    black_tiles.len()
+pub fn part_2(input: &[Directions]) -> usize {
+    lobby_history(input, 100).black_tiles(100).len()
 }
@@ -1,7 +1,9 @@
+use crate::modular::{mul_mod, pow_mod, discrete_log};
+
 #[derive(Debug)]
 pub struct Input {
-    card_public_key: usize,
-    door_public_key: usize,
+    card_public_key: u64,
+    door_public_key: u64,
 }
 
 pub fn generator(input: &str) -> Input {
@@ -11,52 +13,203 @@ pub fn generator(input: &str) -> Input {
     Input {card_public_key, door_public_key}
 }
 
-struct SubjectNumberTransformer {
-    current_value: usize,
-    subject_number: usize,
+impl Input {
+    pub fn new(card_public_key: u64, door_public_key: u64) -> Self {
+        Input {card_public_key, door_public_key}
+    }
+}
+
+pub struct SubjectNumberTransformer {
+    current_value: u64,
+    subject_number: u64,
+    modulus: u64,
 }
 
 impl SubjectNumberTransformer {
-    fn new(subject_number: usize) -> Self {
+    pub fn new(subject_number: u64, modulus: u64) -> Self {
         Self {
             current_value: 1,
-            subject_number
+            subject_number,
+            modulus,
         }
     }
+
+    // Jump ahead without iterating over the skipped values
+    pub fn skip_loops(&mut self, loops: u64) {
+        self.current_value = mul_mod(self.current_value, pow_mod(self.subject_number, loops, self.modulus), self.modulus);
+    }
 }
 
 impl Iterator for SubjectNumberTransformer {
-    type Item = usize;
+    type Item = u64;
 
     fn next(&mut self) -> Option<Self::Item> {
         let result = self.current_value;
-        self.current_value = (self.current_value * self.subject_number) % 20201227;
+        self.current_value = mul_mod(self.current_value, self.subject_number, self.modulus);
         Some(result)
     }
 }
 
-fn search_loop_sizes1(keys: [usize; 2]) -> [usize; 2] {
-    let mut result: [Option<usize>; 2] = [None, None];
-    for (loop_size, public_key) in SubjectNumberTransformer::new(7).enumerate() {
-        if public_key == keys[0] {
-            result[0] = Some(loop_size);
-            if result[1].is_some() {
-                break;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeParams {
+    pub subject_number: u64,
+    pub modulus: u64,
+}
+
+impl Default for HandshakeParams {
+    fn default() -> Self {
+        Self {
+            subject_number: 7,
+            modulus: 20201227,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SimulatedHandshake {
+    pub card_public_key: u64,
+    pub door_public_key: u64,
+    pub encryption_key: u64,
+}
+
+impl SimulatedHandshake {
+    pub fn input(&self) -> Input {
+        Input::new(self.card_public_key, self.door_public_key)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeError {
+    NoLoopSize {
+        public_key: u64,
+    },
+    KeysDisagree {
+        card_side: u64,
+        door_side: u64,
+    },
+}
+
+impl core::fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        match self {
+            HandshakeError::NoLoopSize { public_key } => write!(f, "Public key {} cannot be produced from the subject number", public_key),
+            HandshakeError::KeysDisagree { card_side, door_side } => write!(f, "Card derived encryption key {} but door derived {}", card_side, door_side),
+        }
+    }
+}
+
+impl HandshakeParams {
+    pub fn transformer(&self, subject_number: u64) -> SubjectNumberTransformer {
+        SubjectNumberTransformer::new(subject_number, self.modulus)
+    }
+
+    pub fn transform(&self, subject_number: u64, loop_size: u64) -> u64 {
+        pow_mod(subject_number, loop_size, self.modulus)
+    }
+
+    pub fn public_key(&self, loop_size: u64) -> u64 {
+        self.transform(self.subject_number, loop_size)
+    }
+
+    pub fn find_loop_size(&self, public_key: u64) -> Option<u64> {
+        discrete_log(self.subject_number, public_key, self.modulus)
+    }
+
+    pub fn simulate(&self, card_loop_size: u64, door_loop_size: u64) -> SimulatedHandshake {
+        let card_public_key = self.public_key(card_loop_size);
+        let door_public_key = self.public_key(door_loop_size);
+        SimulatedHandshake {
+            card_public_key,
+            door_public_key,
+            encryption_key: self.transform(door_public_key, card_loop_size),
+        }
+    }
+
+    // Derive the encryption key from both sides, and make sure they agree
+    pub fn verify(&self, input: &Input) -> Result<u64, HandshakeError> {
+        let find_loop_size = |public_key| self.find_loop_size(public_key).ok_or(HandshakeError::NoLoopSize { public_key });
+        let card_loop_size = find_loop_size(input.card_public_key)?;
+        let door_loop_size = find_loop_size(input.door_public_key)?;
+        let card_side = self.transform(input.door_public_key, card_loop_size);
+        let door_side = self.transform(input.card_public_key, door_loop_size);
+        if card_side == door_side {
+            Ok(card_side)
+        } else {
+            Err(HandshakeError::KeysDisagree { card_side, door_side })
+        }
+    }
+}
+
+pub fn part_1(input: &Input) -> u64 {
+    HandshakeParams::default().verify(input).unwrap_or_else(|err| panic!("{}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct RoundTripFailure {
+        card_loop_size: u64,
+        door_loop_size: u64,
+        reason: String,
+    }
+
+    impl core::fmt::Display for RoundTripFailure {
+        fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+            write!(f, "Loop sizes {} and {}: {}", self.card_loop_size, self.door_loop_size, self.reason)
+        }
+    }
+
+    // Simulates handshakes with random loop sizes, and checks that solving them recovers the same
+    // public and encryption keys
+    fn round_trip_harness(params: &HandshakeParams, samples: usize, seed: u64) -> Result<(), RoundTripFailure> {
+        // SplitMix64, so that any seed works
+        let mut state = seed;
+        let mut next_random = || {
+            state = state.wrapping_add(0x9e3779b97f4a7c15);
+            let mut z = state;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+            z ^ (z >> 31)
+        };
+
+        for _ in 0..samples {
+            let card_loop_size = next_random() % params.modulus;
+            let door_loop_size = next_random() % params.modulus;
+            let fail = |reason: String| RoundTripFailure { card_loop_size, door_loop_size, reason };
+
+            let simulated = params.simulate(card_loop_size, door_loop_size);
+            for &(loop_size, public_key) in [(card_loop_size, simulated.card_public_key), (door_loop_size, simulated.door_public_key)].iter() {
+                let recovered = params.find_loop_size(public_key).ok_or_else(|| fail(format!("No loop size found for public key {}", public_key)))?;
+                if loop_size < recovered {
+                    return Err(fail(format!("Recovered loop size {} is larger than {}", recovered, loop_size)));
+                }
+                if params.public_key(recovered) != public_key {
+                    return Err(fail(format!("Recovered loop size {} does not produce public key {}", recovered, public_key)));
+                }
+                // Cross-check the fast exponentiation against the step-by-step transformation
+                let mut transformer = params.transformer(params.subject_number);
+                transformer.skip_loops(loop_size - loop_size % 1024);
+                if transformer.nth((loop_size % 1024) as usize) != Some(public_key) {
+                    return Err(fail(format!("Transformer disagrees with public key {}", public_key)));
+                }
             }
-        } else if public_key == keys[1] {
-            result[1] = Some(loop_size);
-            if result[0].is_some() {
-                break;
+            match params.verify(&simulated.input()) {
+                Ok(encryption_key) if encryption_key == simulated.encryption_key => {},
+                Ok(encryption_key) => return Err(fail(format!("Derived encryption key {} instead of {}", encryption_key, simulated.encryption_key))),
+                Err(err) => return Err(fail(err.to_string())),
             }
         }
+        Ok(())
     }
-    [
-        result[0].unwrap(),
-        result[1].unwrap(),
-    ]
-}
 
-pub fn part_1(input: &Input) -> usize {
-    let [card_loop_size, _door_loop_size] = search_loop_sizes1([input.card_public_key, input.door_public_key]);
-    SubjectNumberTransformer::new(input.door_public_key).nth(card_loop_size).unwrap()
+    #[test]
+    fn round_trip() {
+        let params = HandshakeParams::default();
+        for seed in 0..8 {
+            if let Err(failure) = round_trip_harness(&params, 16, seed) {
+                panic!("Seed {} failed - {}", seed, failure);
+            }
+        }
+    }
 }
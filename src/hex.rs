@@ -0,0 +1,286 @@
+use hashbrown::HashMap;
+
+// Pointy-topped hexagons, with rows going down. East is the positive q direction.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    E,
+    SE,
+    SW,
+    W,
+    NW,
+    NE,
+}
+
+impl Direction {
+    // Clockwise, starting from east
+    pub const ALL: [Direction; 6] = [
+        Direction::E,
+        Direction::SE,
+        Direction::SW,
+        Direction::W,
+        Direction::NW,
+        Direction::NE,
+    ];
+
+    pub fn offset(&self) -> Axial {
+        match self {
+            Direction::E => Axial::new(1, 0),
+            Direction::SE => Axial::new(0, 1),
+            Direction::SW => Axial::new(-1, 1),
+            Direction::W => Axial::new(-1, 0),
+            Direction::NW => Axial::new(0, -1),
+            Direction::NE => Axial::new(1, -1),
+        }
+    }
+
+    pub fn opposite(&self) -> Direction {
+        self.rotated(3)
+    }
+
+    // Positive steps are clockwise
+    pub fn rotated(&self, steps: isize) -> Direction {
+        let index = Self::ALL.iter().position(|direction| direction == self).unwrap() as isize;
+        Self::ALL[(index + steps).rem_euclid(6) as usize]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Axial {
+    pub q: isize,
+    pub r: isize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Cube {
+    pub q: isize,
+    pub r: isize,
+    pub s: isize,
+}
+
+// Doubled-width coordinates - horizontal neighbors are two columns apart, so `col + row` is always even
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Doubled {
+    pub col: isize,
+    pub row: isize,
+}
+
+impl Axial {
+    pub const ORIGIN: Axial = Axial { q: 0, r: 0 };
+
+    pub fn new(q: isize, r: isize) -> Self {
+        Self { q, r }
+    }
+
+    pub fn to_cube(self) -> Cube {
+        Cube {
+            q: self.q,
+            r: self.r,
+            s: -self.q - self.r,
+        }
+    }
+
+    pub fn to_doubled(self) -> Doubled {
+        Doubled {
+            col: 2 * self.q + self.r,
+            row: self.r,
+        }
+    }
+
+    pub fn neighbor(self, direction: Direction) -> Axial {
+        self + direction.offset()
+    }
+
+    pub fn neighbors(self) -> [Axial; 6] {
+        let mut result = [self; 6];
+        for (neighbor, direction) in result.iter_mut().zip(Direction::ALL.iter()) {
+            *neighbor = self.neighbor(*direction);
+        }
+        result
+    }
+
+    pub fn length(self) -> usize {
+        let Cube { q, r, s } = self.to_cube();
+        q.abs().max(r.abs()).max(s.abs()) as usize
+    }
+
+    pub fn distance(self, other: Axial) -> usize {
+        (self - other).length()
+    }
+
+    // Positive steps are clockwise
+    pub fn rotated_around(self, center: Axial, steps: isize) -> Axial {
+        let mut cube = (self - center).to_cube();
+        for _ in 0..steps.rem_euclid(6) {
+            cube = Cube {
+                q: -cube.r,
+                r: -cube.s,
+                s: -cube.q,
+            };
+        }
+        center + cube.to_axial()
+    }
+
+    // Mirror along the line through `center` on which the given coordinate stays constant
+    pub fn reflected_around(self, center: Axial, axis: Axis) -> Axial {
+        let Cube { q, r, s } = (self - center).to_cube();
+        let reflected = match axis {
+            Axis::Q => Cube { q, r: s, s: r },
+            Axis::R => Cube { q: s, r, s: q },
+            Axis::S => Cube { q: r, r: q, s },
+        };
+        center + reflected.to_axial()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    Q,
+    R,
+    S,
+}
+
+impl Cube {
+    pub fn to_axial(self) -> Axial {
+        assert!(self.q + self.r + self.s == 0, "Invalid cube coordinate {:?}", self);
+        Axial::new(self.q, self.r)
+    }
+
+    // Round fractional cube coordinates to the hexagon containing them
+    pub fn round(q: f64, r: f64, s: f64) -> Cube {
+        let mut result = Cube {
+            q: q.round() as isize,
+            r: r.round() as isize,
+            s: s.round() as isize,
+        };
+        let q_diff = (result.q as f64 - q).abs();
+        let r_diff = (result.r as f64 - r).abs();
+        let s_diff = (result.s as f64 - s).abs();
+        if r_diff < q_diff && s_diff < q_diff {
+            result.q = -result.r - result.s;
+        } else if s_diff < r_diff {
+            result.r = -result.q - result.s;
+        } else {
+            result.s = -result.q - result.r;
+        }
+        result
+    }
+}
+
+impl Doubled {
+    pub fn to_axial(self) -> Axial {
+        assert!((self.col + self.row) % 2 == 0, "Invalid doubled coordinate {:?}", self);
+        Axial::new((self.col - self.row) / 2, self.row)
+    }
+}
+
+impl From<Cube> for Axial {
+    fn from(cube: Cube) -> Self {
+        cube.to_axial()
+    }
+}
+
+impl From<Doubled> for Axial {
+    fn from(doubled: Doubled) -> Self {
+        doubled.to_axial()
+    }
+}
+
+impl From<Axial> for Cube {
+    fn from(axial: Axial) -> Self {
+        axial.to_cube()
+    }
+}
+
+impl From<Axial> for Doubled {
+    fn from(axial: Axial) -> Self {
+        axial.to_doubled()
+    }
+}
+
+impl std::ops::Add for Axial {
+    type Output = Axial;
+
+    fn add(self, other: Axial) -> Axial {
+        Axial::new(self.q + other.q, self.r + other.r)
+    }
+}
+
+impl std::ops::Sub for Axial {
+    type Output = Axial;
+
+    fn sub(self, other: Axial) -> Axial {
+        Axial::new(self.q - other.q, self.r - other.r)
+    }
+}
+
+impl std::ops::Mul<isize> for Axial {
+    type Output = Axial;
+
+    fn mul(self, factor: isize) -> Axial {
+        Axial::new(self.q * factor, self.r * factor)
+    }
+}
+
+// Clockwise, starting from the south-west corner
+pub fn ring(center: Axial, radius: usize) -> Vec<Axial> {
+    if radius == 0 {
+        return vec![center];
+    }
+    let mut result = Vec::with_capacity(6 * radius);
+    let mut hex = center + Direction::SW.offset() * radius as isize;
+    for &direction in [Direction::NW, Direction::NE, Direction::E, Direction::SE, Direction::SW, Direction::W].iter() {
+        for _ in 0..radius {
+            result.push(hex);
+            hex = hex.neighbor(direction);
+        }
+    }
+    result
+}
+
+// All the hexagons up to `radius` from the center, ring by ring
+pub fn spiral(center: Axial, radius: usize) -> Vec<Axial> {
+    (0..=radius).flat_map(|radius| ring(center, radius)).collect()
+}
+
+pub fn line(from: Axial, to: Axial) -> Vec<Axial> {
+    let steps = from.distance(to);
+    if steps == 0 {
+        return vec![from];
+    }
+    let from = from.to_cube();
+    let to = to.to_cube();
+    // Nudging avoids landing exactly on the edge between two hexagons
+    let lerp = |a: isize, b: isize, t: f64, nudge: f64| a as f64 + (b - a) as f64 * t + nudge;
+    (0..=steps).map(|step| {
+        let t = step as f64 / steps as f64;
+        Cube::round(lerp(from.q, to.q, t, 1e-6), lerp(from.r, to.r, t, 2e-6), lerp(from.s, to.s, t, -3e-6)).to_axial()
+    }).collect()
+}
+
+// Hexagons that are not given are drawn as `empty`
+pub fn render_ascii(tiles: impl IntoIterator<Item = (Axial, char)>, empty: char) -> String {
+    let tiles: HashMap<Doubled, char> = tiles.into_iter().map(|(axial, c)| (axial.to_doubled(), c)).collect();
+    if tiles.is_empty() {
+        return String::new();
+    }
+    let min_col = tiles.keys().map(|doubled| doubled.col).min().unwrap();
+    let max_col = tiles.keys().map(|doubled| doubled.col).max().unwrap();
+    let min_row = tiles.keys().map(|doubled| doubled.row).min().unwrap();
+    let max_row = tiles.keys().map(|doubled| doubled.row).max().unwrap();
+
+    let mut result = String::new();
+    for row in min_row..=max_row {
+        let mut line = String::new();
+        for col in min_col..=max_col {
+            line.push(if (col + row).rem_euclid(2) == 0 {
+                tiles.get(&Doubled { col, row }).copied().unwrap_or(empty)
+            } else {
+                ' '
+            });
+        }
+        result.push_str(line.trim_end());
+        result.push('\n');
+    }
+    result
+}
@@ -22,4 +22,6 @@ pub mod day21;
 pub mod day22;
 pub mod day23;
 pub mod day24;
-pub mod day25;
\ No newline at end of file
+pub mod day25;
+pub mod hex;
+pub mod modular;
@@ -0,0 +1,64 @@
+use hashbrown::HashMap;
+use hashbrown::hash_map::Entry;
+
+pub fn mul_mod(a: u64, b: u64, modulus: u64) -> u64 {
+    (a as u128 * b as u128 % modulus as u128) as u64
+}
+
+pub fn pow_mod(base: u64, mut exponent: u64, modulus: u64) -> u64 {
+    let mut result = 1 % modulus;
+    let mut base = base % modulus;
+    while 0 < exponent {
+        if exponent & 1 == 1 {
+            result = mul_mod(result, base, modulus);
+        }
+        base = mul_mod(base, base, modulus);
+        exponent >>= 1;
+    }
+    result
+}
+
+// `None` if `value` and `modulus` are not coprime
+pub fn inverse_mod(value: u64, modulus: u64) -> Option<u64> {
+    let (mut old_r, mut r) = (value as i128 % modulus as i128, modulus as i128);
+    let (mut old_s, mut s) = (1i128, 0i128);
+    while r != 0 {
+        let quotient = old_r / r;
+        let next_r = old_r - quotient * r;
+        old_r = r;
+        r = next_r;
+        let next_s = old_s - quotient * s;
+        old_s = s;
+        s = next_s;
+    }
+    if old_r != 1 {
+        return None;
+    }
+    Some(old_s.rem_euclid(modulus as i128) as u64)
+}
+
+// The smallest `x` such that `base^x == target (mod modulus)`, using baby-step giant-step.
+// `base` must be coprime to `modulus`.
+pub fn discrete_log(base: u64, target: u64, modulus: u64) -> Option<u64> {
+    let target = target % modulus;
+    let steps = (modulus as f64).sqrt().ceil() as u64;
+
+    let mut baby_steps = HashMap::with_capacity(steps as usize);
+    let mut value = 1 % modulus;
+    for j in 0..steps {
+        if let Entry::Vacant(entry) = baby_steps.entry(value) {
+            entry.insert(j);
+        }
+        value = mul_mod(value, base, modulus);
+    }
+
+    let giant_step = inverse_mod(pow_mod(base, steps, modulus), modulus)?;
+    let mut gamma = target;
+    for i in 0..steps {
+        if let Some(&j) = baby_steps.get(&gamma) {
+            return Some(i * steps + j);
+        }
+        gamma = mul_mod(gamma, giant_step, modulus);
+    }
+    None
+}